@@ -6,7 +6,7 @@ if [[ $ext -ne 0 ]]; then
 fi;
 
 sudo setcap cap_net_admin=eip ./target/release/rust_tcp
-MY_IP=192.168.1.1 ./target/release/rust_tcp &
+./target/release/rust_tcp &
 pid=$!
 sudo ip a add 192.168.1.1/24 dev tun0
 sudo ip link set up dev tun0
@@ -0,0 +1,235 @@
+use std::{io, net::Ipv4Addr, ops::RangeInclusive, time::Duration};
+
+use crate::{
+    congestion::{CongestionControlFactory, NewReno},
+    tcp,
+};
+
+/// Interface the stack is attached to by default
+const DEFAULT_INTERFACE: &str = "tun0";
+/// MTU of the interface by default
+const DEFAULT_MTU: usize = 1500;
+/// Smallest MTU an IPv4 interface may have. (RFC 791)
+const MIN_MTU: usize = 68;
+/// Longest interface name, not counting the nul terminator. (IFNAMSIZ - 1)
+const MAX_INTERFACE_LEN: usize = 15;
+/// Capacity of the receive buffer of a connection by default
+const DEFAULT_RECV_BUFFER_SIZE: usize = 256 * 1024;
+/// Largest receive buffer, the most a scaled window can advertise. (RFC 7323 - Section 2.3)
+const MAX_RECV_BUFFER_SIZE: usize = (u16::MAX as usize) << tcp::MAX_WINDOW_SHIFT;
+/// Local ports outgoing connections are bound to by default. (RFC 6335 - Section 6)
+const DEFAULT_EPHEMERAL_PORTS: RangeInclusive<u16> = 49152..=65535;
+
+/// Configuration of the TCP stack, passed to `Tcp::init`
+#[derive(Debug, Clone)]
+pub struct TcpConfig {
+    pub(crate) interface: String,
+    pub(crate) address: Ipv4Addr,
+    pub(crate) prefix_len: u8,
+    pub(crate) mtu: usize,
+    pub(crate) msl: Duration,
+    pub(crate) retransmission_limit: u32,
+    pub(crate) ack_delay: Duration,
+    pub(crate) congestion_control: CongestionControlFactory,
+    pub(crate) ecn: bool,
+    pub(crate) recv_buffer_size: usize,
+    pub(crate) ephemeral_ports: RangeInclusive<u16>,
+    pub(crate) isn_seed: Option<u64>,
+}
+
+impl TcpConfig {
+    pub fn builder() -> TcpConfigBuilder {
+        TcpConfigBuilder::default()
+    }
+
+    /// Name of the TUN interface
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    /// Local IPv4 address of the stack
+    pub fn address(&self) -> Ipv4Addr {
+        self.address
+    }
+
+    /// Prefix length of the subnet the interface is configured with
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// MTU of the interface
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+}
+
+/// Builder of a `TcpConfig`, only the local address has no default
+#[derive(Debug, Clone)]
+pub struct TcpConfigBuilder {
+    interface: String,
+    address: Option<(Ipv4Addr, u8)>,
+    mtu: usize,
+    msl: Duration,
+    retransmission_limit: u32,
+    ack_delay: Duration,
+    congestion_control: CongestionControlFactory,
+    ecn: bool,
+    recv_buffer_size: usize,
+    ephemeral_ports: RangeInclusive<u16>,
+    isn_seed: Option<u64>,
+}
+
+impl Default for TcpConfigBuilder {
+    fn default() -> Self {
+        TcpConfigBuilder {
+            interface: DEFAULT_INTERFACE.to_owned(),
+            address: None,
+            mtu: DEFAULT_MTU,
+            msl: tcp::DEFAULT_MSL,
+            retransmission_limit: tcp::DEFAULT_RETRANSMISSION_LIMIT,
+            ack_delay: tcp::DEFAULT_ACK_DELAY,
+            congestion_control: |mss| Box::new(NewReno::new(mss)),
+            ecn: true,
+            recv_buffer_size: DEFAULT_RECV_BUFFER_SIZE,
+            ephemeral_ports: DEFAULT_EPHEMERAL_PORTS,
+            isn_seed: None,
+        }
+    }
+}
+
+impl TcpConfigBuilder {
+    /// Sets the name of the TUN interface. (`tun0` by default)
+    pub fn interface(mut self, name: impl Into<String>) -> Self {
+        self.interface = name.into();
+        self
+    }
+
+    /// Sets the local IPv4 address of the stack, and the prefix length of the subnet the
+    /// interface is configured with.
+    pub fn address(mut self, address: Ipv4Addr, prefix_len: u8) -> Self {
+        self.address = Some((address, prefix_len));
+        self
+    }
+
+    /// Sets the MTU of the interface. (1500 bytes by default)
+    pub fn mtu(mut self, mtu: usize) -> Self {
+        self.mtu = mtu;
+        self
+    }
+
+    /// Sets the maximum segment lifetime, connections stay in TIME-WAIT for twice as long once
+    /// closed. It can be at most an hour.
+    pub fn msl(mut self, msl: Duration) -> Self {
+        self.msl = msl;
+        self
+    }
+
+    /// Sets the number of times an unacknowledged segment is retransmitted before the connection
+    /// is aborted, the streams can override it.
+    pub fn retransmission_limit(mut self, limit: u32) -> Self {
+        self.retransmission_limit = limit;
+        self
+    }
+
+    /// Sets the time the acknowledgment of in-order data is delayed for, in the hope of
+    /// piggybacking it on data. It can be at most 500 ms, and zero acknowledges every segment
+    /// right away. (200 ms by default)
+    pub fn ack_delay(mut self, delay: Duration) -> Self {
+        self.ack_delay = delay;
+        self
+    }
+
+    /// Sets the congestion control of new connections, created from their maximum segment size.
+    /// (NewReno by default, `|mss| Box::new(Cubic::new(mss))` picks CUBIC, and likewise for `Bbr`)
+    pub fn congestion_control(mut self, congestion_control: CongestionControlFactory) -> Self {
+        self.congestion_control = congestion_control;
+        self
+    }
+
+    /// Sets whether new connections negotiate Explicit Congestion Notification, letting routers
+    /// mark congestion rather than drop packets. (RFC 3168, enabled by default)
+    pub fn ecn(mut self, enabled: bool) -> Self {
+        self.ecn = enabled;
+        self
+    }
+
+    /// Sets the capacity of the receive buffer of each connection, the window advertised to the
+    /// peer is the free space in it, so a slow reader throttles the sender. Windows past 64 KiB
+    /// are only advertised if the peer supports window scaling. (256 KiB by default)
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = size;
+        self
+    }
+
+    /// Sets the range of local ports outgoing connections are bound to when none is given.
+    pub fn ephemeral_ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.ephemeral_ports = ports;
+        self
+    }
+
+    /// Makes the initial sequence numbers deterministic, only depending on `seed` and the order
+    /// the connections are created in. This is meant for reproducible tests, as it makes
+    /// sequence numbers predictable.
+    pub fn isn_seed(mut self, seed: u64) -> Self {
+        self.isn_seed = Some(seed);
+        self
+    }
+
+    /// Validates the configuration
+    pub fn build(self) -> io::Result<TcpConfig> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg);
+
+        if self.interface.is_empty()
+            || self.interface.len() > MAX_INTERFACE_LEN
+            || self.interface.contains(['/', '\0'])
+            || self.interface.chars().any(char::is_whitespace)
+        {
+            return Err(invalid("invalid interface name!"));
+        }
+
+        let (address, prefix_len) = self
+            .address
+            .ok_or_else(|| invalid("no local address configured!"))?;
+        if address.is_unspecified() || address.is_broadcast() || address.is_multicast() {
+            return Err(invalid("invalid local address!"));
+        }
+        if prefix_len > 32 {
+            return Err(invalid("invalid prefix length!"));
+        }
+
+        if !(MIN_MTU..=u16::MAX as usize).contains(&self.mtu) {
+            return Err(invalid("invalid MTU!"));
+        }
+
+        if self.msl > tcp::MAX_MSL {
+            return Err(invalid("invalid maximum segment lifetime!"));
+        }
+
+        if self.ack_delay > tcp::MAX_ACK_DELAY {
+            return Err(invalid("invalid acknowledgment delay!"));
+        }
+
+        if !(1..=MAX_RECV_BUFFER_SIZE).contains(&self.recv_buffer_size) {
+            return Err(invalid("invalid receive buffer size!"));
+        }
+
+        if self.ephemeral_ports.is_empty() || *self.ephemeral_ports.start() == 0 {
+            return Err(invalid("invalid ephemeral port range!"));
+        }
+
+        Ok(TcpConfig {
+            interface: self.interface,
+            address,
+            prefix_len,
+            mtu: self.mtu,
+            msl: self.msl,
+            retransmission_limit: self.retransmission_limit,
+            ack_delay: self.ack_delay,
+            congestion_control: self.congestion_control,
+            ecn: self.ecn,
+            recv_buffer_size: self.recv_buffer_size,
+            ephemeral_ports: self.ephemeral_ports,
+            isn_seed: self.isn_seed,
+        })
+    }
+}
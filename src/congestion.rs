@@ -0,0 +1,110 @@
+use std::{
+    cmp, fmt,
+    time::{Duration, Instant},
+};
+
+mod bbr;
+mod cubic;
+mod newreno;
+
+pub use bbr::Bbr;
+pub use cubic::Cubic;
+pub use newreno::NewReno;
+
+/// Creates the congestion control of a connection, given its maximum segment size
+pub type CongestionControlFactory = fn(mss: usize) -> Box<dyn CongestionControl>;
+
+/// Congestion control algorithm of a connection. (RFC 5681)
+///
+/// The connection keeps track of the losses and retransmissions, and only asks the algorithm for
+/// the congestion window limiting the bytes in flight.
+pub trait CongestionControl: fmt::Debug + Send {
+    /// Congestion window, in bytes (cwnd)
+    fn cwnd(&self) -> usize;
+
+    /// Slow start threshold, in bytes (ssthresh)
+    fn ssthresh(&self) -> usize;
+
+    /// Called when new data is acknowledged, outside of a loss recovery
+    fn on_ack(&mut self, ack: &Ack);
+
+    /// Called when a loss is detected through duplicate acknowledgments or SACK, as a loss
+    /// recovery starts. The acknowledgment was already passed to `on_ack` if it acknowledged new
+    /// data.
+    fn on_loss(&mut self, ack: &Ack);
+
+    /// Called when the loss recovery ends, i.e. all the data outstanding when it started is
+    /// acknowledged
+    fn on_recovery_end(&mut self, _ack: &Ack) {}
+
+    /// Called for the acknowledgments received during a loss recovery, but the one ending it
+    fn on_recovery_ack(&mut self, _ack: &Ack) {}
+
+    /// Called when the retransmission timer expires, with the bytes in flight and the maximum
+    /// segment size
+    fn on_rto(&mut self, flight_size: usize, mss: usize);
+
+    /// Rate new segments are paced at, in bytes per second, or `None` to send them as soon as
+    /// the windows allow
+    fn pacing_rate(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// An acknowledgment, as seen by the congestion control
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Ack {
+    /// time the acknowledgment arrived at
+    pub now: Instant,
+    /// bytes newly acknowledged
+    pub acked: usize,
+    /// bytes in flight before the acknowledgment (FlightSize)
+    pub flight_size: usize,
+    /// maximum segment size of the connection
+    pub mss: usize,
+    /// round-trip time measured from the acknowledgment, if any
+    pub rtt: Option<Duration>,
+    /// oldest unacknowledged sequence number after the acknowledgment (SND.UNA)
+    pub una: u32,
+    /// next sequence number to be sent (SND.NXT)
+    pub nxt: u32,
+    /// bytes delivered to the peer so far, including the SACKed ones (C.delivered)
+    pub delivered: u64,
+    /// delivery rate measured from the acknowledgment, if it delivered anything
+    pub rate_sample: Option<RateSample>,
+}
+
+/// Delivery rate over the interval between sending a segment and its delivery.
+/// (draft-cheng-iccrg-delivery-rate-estimation - Section 3.3)
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct RateSample {
+    /// bytes delivered over the interval (rs.delivered)
+    pub delivered: u64,
+    /// length of the interval, the longer of its send and acknowledgment phases (rs.interval)
+    pub interval: Duration,
+    /// bytes delivered when the most recently delivered segment was sent (rs.prior_delivered)
+    pub prior_delivered: u64,
+    /// the segment was sent while the application had nothing more to send, so the rate only
+    /// bounds the available bandwidth from below (rs.is_app_limited)
+    pub app_limited: bool,
+}
+
+impl RateSample {
+    /// Delivery rate, in bytes per second
+    pub fn delivery_rate(&self) -> u64 {
+        (self.delivered as u128 * 1_000_000_000 / cmp::max(self.interval.as_nanos(), 1)) as u64
+    }
+}
+
+/// Initial congestion window for a given maximum segment size. (RFC 5681 - Section 3.1)
+pub(crate) fn initial_window(mss: usize) -> usize {
+    if mss > 2190 {
+        2 * mss
+    } else if mss > 1095 {
+        3 * mss
+    } else {
+        4 * mss
+    }
+}
@@ -0,0 +1,536 @@
+use std::{
+    cmp,
+    collections::{hash_map::RandomState, VecDeque},
+    hash::BuildHasher,
+    time::{Duration, Instant},
+};
+
+use super::{initial_window, Ack, CongestionControl};
+
+/// Pacing and window gain of Startup, the smallest one doubling the sending rate every round
+/// (2/ln(2))
+const HIGH_GAIN: f64 = 2.885;
+/// Pacing gain of Drain, emptying the queue Startup built
+const DRAIN_GAIN: f64 = 1.0 / HIGH_GAIN;
+/// Window gain of ProbeBW, leaving room for delayed and stretched acknowledgments
+const CWND_GAIN: f64 = 2.0;
+/// Pacing gains ProbeBW cycles through, a phase lasting a minimum RTT
+const PACING_GAIN_CYCLE: [f64; 8] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+/// Rounds the bottleneck bandwidth is the maximum delivery rate of
+const BTL_BW_FILTER_LEN: u64 = 10;
+/// Time the minimum RTT is the minimum of, ProbeRTT measures it again once it expires
+const MIN_RTT_FILTER_LEN: Duration = Duration::from_secs(10);
+/// Time ProbeRTT holds the window down for
+const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+/// Smallest window, in segments, keeping the pipe full with delayed acknowledgments
+const MIN_PIPE_CWND: usize = 4;
+/// Growth of the bottleneck bandwidth over a round that Startup expects while the pipe isn't full
+const FULL_BW_THRESH: f64 = 1.25;
+/// Rounds without such growth after which the pipe is deemed full
+const FULL_BW_COUNT: u32 = 3;
+
+/// Phases of BBR. (draft-cardwell-iccrg-bbr-congestion-control-00 - Section 3.4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// grows the sending rate exponentially until the bottleneck bandwidth is found
+    Startup,
+    /// drains the queue Startup built
+    Drain,
+    /// cycles the sending rate around the bottleneck bandwidth, probing for more
+    ProbeBw,
+    /// lowers the window to drain the queue and measure the minimum RTT
+    ProbeRtt,
+}
+
+/// BBR congestion control. (draft-cardwell-iccrg-bbr-congestion-control-00)
+///
+/// Rather than reacting to losses, BBR models the path from the delivery rate samples and the
+/// RTT: the sending rate is paced at the bottleneck bandwidth, and the bytes in flight are kept
+/// around the bandwidth-delay product.
+#[derive(Debug)]
+pub struct Bbr {
+    mode: Mode,
+    cwnd: usize,
+    /// window before the last loss recovery or ProbeRTT, restored once it ends
+    prior_cwnd: usize,
+    mss: usize,
+    /// rate segments are paced at, in bytes per second
+    pacing_rate: u64,
+    pacing_gain: f64,
+    cwnd_gain: f64,
+    /// delivery rates of the last rounds as (round, rate), in decreasing rate order so that the
+    /// first one is the bottleneck bandwidth (BBR.BtlBw)
+    btl_bw_filter: VecDeque<(u64, u64)>,
+    /// minimum RTT of the path, once measured (BBR.RTprop)
+    min_rtt: Option<Duration>,
+    /// time `min_rtt` was measured at (BBR.rtprop_stamp)
+    min_rtt_stamp: Instant,
+    /// round trips so far (BBR.round_count)
+    round_count: u64,
+    /// delivered bytes ending the current round (BBR.next_round_delivered)
+    next_round_delivered: u64,
+    /// the last acknowledgment started a round (BBR.round_start)
+    round_start: bool,
+    /// bottleneck bandwidth the growth of Startup is measured against (BBR.full_bw)
+    full_bw: u64,
+    /// rounds without growth (BBR.full_bw_count)
+    full_bw_count: u32,
+    /// the bottleneck bandwidth was found (BBR.filled_pipe)
+    filled_pipe: bool,
+    /// current phase of the ProbeBW gain cycle
+    cycle_index: usize,
+    /// time the current phase started at
+    cycle_stamp: Instant,
+    /// time ProbeRTT may end at, once the window is down
+    probe_rtt_done_stamp: Option<Instant>,
+    /// ProbeRTT lasted a round
+    probe_rtt_round_done: bool,
+    /// a loss recovery is in progress
+    in_recovery: bool,
+    /// the window only grows by what is delivered, during the first round of a loss recovery
+    packet_conservation: bool,
+}
+
+impl Bbr {
+    pub fn new(mss: usize) -> Self {
+        let now = Instant::now();
+        let cwnd = initial_window(mss);
+        Bbr {
+            mode: Mode::Startup,
+            cwnd,
+            prior_cwnd: cwnd,
+            mss,
+            // Until the RTT is measured, assume it is a millisecond
+            pacing_rate: (HIGH_GAIN * cwnd as f64 * 1000.0) as u64,
+            pacing_gain: HIGH_GAIN,
+            cwnd_gain: HIGH_GAIN,
+            btl_bw_filter: VecDeque::new(),
+            min_rtt: None,
+            min_rtt_stamp: now,
+            round_count: 0,
+            next_round_delivered: 0,
+            round_start: false,
+            full_bw: 0,
+            full_bw_count: 0,
+            filled_pipe: false,
+            cycle_index: 0,
+            cycle_stamp: now,
+            probe_rtt_done_stamp: None,
+            probe_rtt_round_done: false,
+            in_recovery: false,
+            packet_conservation: false,
+        }
+    }
+
+    /// Bottleneck bandwidth, in bytes per second (BBR.BtlBw)
+    fn btl_bw(&self) -> u64 {
+        self.btl_bw_filter.front().map_or(0, |&(_, rate)| rate)
+    }
+
+    /// Bandwidth-delay product scaled by `gain`, or the initial window until the path is
+    /// measured (BBRInflight)
+    fn bdp(&self, gain: f64) -> usize {
+        match self.min_rtt {
+            Some(min_rtt) if self.btl_bw() > 0 => {
+                (gain * self.btl_bw() as f64 * min_rtt.as_secs_f64()) as usize
+            }
+            _ => initial_window(self.mss),
+        }
+    }
+
+    /// Window to keep in flight, with room for the segments the receiver holds back while
+    /// delaying its acknowledgments (BBRUpdateTargetCwnd)
+    fn target_cwnd(&self) -> usize {
+        self.bdp(self.cwnd_gain) + 3 * self.mss
+    }
+
+    /// Window to restore once a loss recovery or ProbeRTT ends (BBRSaveCwnd)
+    fn save_cwnd(&self) -> usize {
+        if self.in_recovery || self.mode == Mode::ProbeRtt {
+            cmp::max(self.prior_cwnd, self.cwnd)
+        } else {
+            self.cwnd
+        }
+    }
+
+    fn enter_startup(&mut self) {
+        self.mode = Mode::Startup;
+        self.pacing_gain = HIGH_GAIN;
+        self.cwnd_gain = HIGH_GAIN;
+    }
+
+    /// Enters ProbeBW at a random phase of the gain cycle, but the one draining the queue
+    fn enter_probe_bw(&mut self, now: Instant) {
+        self.mode = Mode::ProbeBw;
+        self.cwnd_gain = CWND_GAIN;
+        let index = RandomState::new().hash_one(now) as usize % (PACING_GAIN_CYCLE.len() - 1);
+        self.cycle_index = if index == 0 { 0 } else { index + 1 };
+        self.cycle_stamp = now;
+        self.pacing_gain = PACING_GAIN_CYCLE[self.cycle_index];
+    }
+
+    /// Updates the model of the path with an acknowledgment and moves between the phases.
+    /// (Section 4.1)
+    fn update_model(&mut self, ack: &Ack) {
+        let inflight = ack.flight_size.saturating_sub(ack.acked);
+
+        // A round ends once a segment sent after it started is delivered (Section 4.1.1.3)
+        self.round_start = false;
+        if let Some(rate_sample) = &ack.rate_sample {
+            if rate_sample.prior_delivered >= self.next_round_delivered {
+                self.next_round_delivered = ack.delivered;
+                self.round_count += 1;
+                self.round_start = true;
+                self.packet_conservation = false;
+            }
+
+            // Application-limited samples only underestimate the bandwidth (Section 4.1.1.5)
+            let rate = rate_sample.delivery_rate();
+            if rate >= self.btl_bw() || !rate_sample.app_limited {
+                while self
+                    .btl_bw_filter
+                    .back()
+                    .is_some_and(|&(_, other)| other <= rate)
+                {
+                    self.btl_bw_filter.pop_back();
+                }
+                self.btl_bw_filter.push_back((self.round_count, rate));
+            }
+            while self.btl_bw_filter.len() > 1
+                && self.btl_bw_filter[0].0 + BTL_BW_FILTER_LEN <= self.round_count
+            {
+                self.btl_bw_filter.pop_front();
+            }
+        }
+
+        // Move on to the next phase of the gain cycle (Section 4.3.4.4)
+        if self.mode == Mode::ProbeBw {
+            let full_length = ack.now - self.cycle_stamp > self.min_rtt.unwrap_or_default();
+            let next_phase = if self.pacing_gain > 1.0 {
+                full_length && ack.flight_size >= self.bdp(self.pacing_gain)
+            } else if self.pacing_gain < 1.0 {
+                full_length || ack.flight_size <= self.bdp(1.0)
+            } else {
+                full_length
+            };
+            if next_phase {
+                self.cycle_index = (self.cycle_index + 1) % PACING_GAIN_CYCLE.len();
+                self.cycle_stamp = ack.now;
+                self.pacing_gain = PACING_GAIN_CYCLE[self.cycle_index];
+            }
+        }
+
+        // Startup ends once the bandwidth stops growing (Section 4.3.2.2)
+        let app_limited = ack
+            .rate_sample
+            .as_ref()
+            .is_some_and(|rate_sample| rate_sample.app_limited);
+        if !self.filled_pipe && self.round_start && !app_limited {
+            if self.btl_bw() as f64 >= self.full_bw as f64 * FULL_BW_THRESH {
+                self.full_bw = self.btl_bw();
+                self.full_bw_count = 0;
+            } else {
+                self.full_bw_count += 1;
+                self.filled_pipe = self.full_bw_count >= FULL_BW_COUNT;
+            }
+        }
+        if self.mode == Mode::Startup && self.filled_pipe {
+            self.mode = Mode::Drain;
+            self.pacing_gain = DRAIN_GAIN;
+            self.cwnd_gain = HIGH_GAIN;
+        }
+        if self.mode == Mode::Drain && inflight <= self.bdp(1.0) {
+            self.enter_probe_bw(ack.now);
+        }
+
+        // The minimum RTT expires, so that path changes are noticed (Section 4.1.2.3)
+        let min_rtt_expired = ack.now > self.min_rtt_stamp + MIN_RTT_FILTER_LEN;
+        if let Some(rtt) = ack.rtt {
+            if min_rtt_expired || self.min_rtt.is_none_or(|min_rtt| rtt <= min_rtt) {
+                self.min_rtt = Some(rtt);
+                self.min_rtt_stamp = ack.now;
+            }
+        }
+
+        // Measure the minimum RTT again with an empty queue (Section 4.3.5)
+        if self.mode != Mode::ProbeRtt && min_rtt_expired {
+            self.prior_cwnd = self.save_cwnd();
+            self.mode = Mode::ProbeRtt;
+            self.pacing_gain = 1.0;
+            self.cwnd_gain = 1.0;
+            self.probe_rtt_done_stamp = None;
+        }
+        if self.mode == Mode::ProbeRtt {
+            match self.probe_rtt_done_stamp {
+                None if inflight <= MIN_PIPE_CWND * self.mss => {
+                    self.probe_rtt_done_stamp = Some(ack.now + PROBE_RTT_DURATION);
+                    self.probe_rtt_round_done = false;
+                    self.next_round_delivered = ack.delivered;
+                }
+                Some(done) => {
+                    self.probe_rtt_round_done |= self.round_start;
+                    if self.probe_rtt_round_done && ack.now >= done {
+                        self.min_rtt_stamp = ack.now;
+                        self.cwnd = cmp::max(self.cwnd, self.prior_cwnd);
+                        if self.filled_pipe {
+                            self.enter_probe_bw(ack.now);
+                        } else {
+                            self.enter_startup();
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+
+        self.update_pacing_rate();
+    }
+
+    /// Paces at the bottleneck bandwidth scaled by the pacing gain, which only lowers the rate
+    /// once the pipe is full (BBRSetPacingRate)
+    fn update_pacing_rate(&mut self) {
+        let rate = match (self.btl_bw(), self.min_rtt) {
+            (0, Some(min_rtt)) if !min_rtt.is_zero() => self.cwnd as f64 / min_rtt.as_secs_f64(),
+            (0, _) => return,
+            (btl_bw, _) => btl_bw as f64,
+        };
+        let rate = (self.pacing_gain * rate) as u64;
+        if self.filled_pipe || rate > self.pacing_rate {
+            self.pacing_rate = rate;
+        }
+    }
+
+    /// Grows the window towards its target, by what was delivered (BBRSetCwnd)
+    fn update_cwnd(&mut self, ack: &Ack) {
+        let inflight = ack.flight_size.saturating_sub(ack.acked);
+        let target = self.target_cwnd();
+        if self.packet_conservation {
+            self.cwnd = cmp::max(self.cwnd, inflight + ack.acked);
+        } else if self.filled_pipe {
+            self.cwnd = cmp::min(self.cwnd + ack.acked, target);
+        } else if self.cwnd < target || ack.delivered < initial_window(self.mss) as u64 {
+            self.cwnd += ack.acked;
+        }
+        self.cwnd = cmp::max(self.cwnd, MIN_PIPE_CWND * self.mss);
+        if self.mode == Mode::ProbeRtt {
+            self.cwnd = cmp::min(self.cwnd, MIN_PIPE_CWND * self.mss);
+        }
+    }
+}
+
+impl CongestionControl for Bbr {
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    /// BBR doesn't use a slow start threshold
+    fn ssthresh(&self) -> usize {
+        usize::MAX
+    }
+
+    fn on_ack(&mut self, ack: &Ack) {
+        self.update_model(ack);
+        self.update_cwnd(ack);
+    }
+
+    fn on_loss(&mut self, ack: &Ack) {
+        // Only send as much as is delivered for a round, allowing for the fast retransmit
+        // (Section 4.2.3.4) The model is only missing the acknowledgment if it didn't go through
+        // `on_ack`, i.e. it is a duplicate one.
+        if ack.acked == 0 {
+            self.update_model(ack);
+        }
+        self.prior_cwnd = self.save_cwnd();
+        self.in_recovery = true;
+        self.packet_conservation = true;
+        self.next_round_delivered = ack.delivered;
+        let inflight = ack.flight_size.saturating_sub(ack.acked);
+        self.cwnd = inflight + cmp::max(ack.acked, ack.mss);
+    }
+
+    fn on_recovery_ack(&mut self, ack: &Ack) {
+        self.on_ack(ack);
+    }
+
+    fn on_recovery_end(&mut self, ack: &Ack) {
+        self.in_recovery = false;
+        self.packet_conservation = false;
+        self.on_ack(ack);
+        self.cwnd = cmp::max(self.cwnd, self.prior_cwnd);
+    }
+
+    fn on_rto(&mut self, _flight_size: usize, mss: usize) {
+        self.prior_cwnd = self.save_cwnd();
+        self.in_recovery = false;
+        self.packet_conservation = false;
+        self.cwnd = mss;
+    }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        Some(self.pacing_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::congestion::RateSample;
+
+    const MSS: usize = 1000;
+    const RTT: Duration = Duration::from_millis(10);
+
+    /// Path delivering the acknowledged bytes over a round trip, one acknowledgment per round
+    struct Path {
+        now: Instant,
+        delivered: u64,
+    }
+
+    impl Path {
+        fn new() -> Self {
+            Path {
+                now: Instant::now(),
+                delivered: 0,
+            }
+        }
+
+        /// Acknowledgment of `bytes` delivered over the last round trip, with `flight_size` bytes
+        /// in flight before it
+        fn ack(&mut self, bytes: u64, flight_size: usize, app_limited: bool) -> Ack {
+            self.now += RTT;
+            let prior_delivered = self.delivered;
+            self.delivered += bytes;
+            Ack {
+                now: self.now,
+                acked: MSS,
+                flight_size,
+                mss: MSS,
+                rtt: Some(RTT),
+                una: 0,
+                nxt: 0,
+                delivered: self.delivered,
+                rate_sample: Some(RateSample {
+                    delivered: bytes,
+                    interval: RTT,
+                    prior_delivered,
+                    app_limited,
+                }),
+            }
+        }
+    }
+
+    /// Goes through Startup and Drain, with a bottleneck bandwidth of 40 kB per round trip
+    fn probe_bw() -> (Bbr, Path) {
+        let mut bbr = Bbr::new(MSS);
+        let mut path = Path::new();
+        for bytes in [10_000, 20_000, 40_000] {
+            bbr.on_ack(&path.ack(bytes, 200_000, false));
+            assert_eq!(bbr.mode, Mode::Startup);
+        }
+        for _ in 1..FULL_BW_COUNT {
+            bbr.on_ack(&path.ack(40_000, 200_000, false));
+            assert!(!bbr.filled_pipe);
+        }
+        // Application-limited rounds don't tell whether the pipe is full
+        bbr.on_ack(&path.ack(40_000, 200_000, true));
+        assert!(!bbr.filled_pipe);
+
+        bbr.on_ack(&path.ack(40_000, 200_000, false));
+        assert!(bbr.filled_pipe);
+        assert_eq!(bbr.mode, Mode::Drain);
+        assert_eq!(bbr.pacing_gain, DRAIN_GAIN);
+
+        // Drain lasts until the flight is down to the bandwidth-delay product
+        bbr.on_ack(&path.ack(40_000, 20_000, false));
+        assert_eq!(bbr.mode, Mode::ProbeBw);
+        (bbr, path)
+    }
+
+    #[test]
+    fn startup_drains_into_probe_bw() {
+        let (bbr, _) = probe_bw();
+        assert_eq!(bbr.btl_bw(), 4_000_000);
+        assert_eq!(bbr.cwnd_gain, CWND_GAIN);
+        // Never starting with the phase draining the queue
+        assert_ne!(bbr.cycle_index, 1);
+        assert_eq!(bbr.pacing_gain, PACING_GAIN_CYCLE[bbr.cycle_index]);
+    }
+
+    #[test]
+    fn probe_rtt_holds_the_window_down() {
+        let (mut bbr, mut path) = probe_bw();
+
+        path.now += MIN_RTT_FILTER_LEN;
+        bbr.on_ack(&path.ack(40_000, 200_000, false));
+        assert_eq!(bbr.mode, Mode::ProbeRtt);
+        assert_eq!(bbr.pacing_gain, 1.0);
+        assert_eq!(bbr.cwnd, MIN_PIPE_CWND * MSS);
+        assert!(bbr.probe_rtt_done_stamp.is_none());
+
+        // The timer starts once the flight is down to the window
+        bbr.on_ack(&path.ack(4_000, (MIN_PIPE_CWND + 1) * MSS, false));
+        let done = bbr.probe_rtt_done_stamp.unwrap();
+        assert_eq!(done, path.now + PROBE_RTT_DURATION);
+
+        // A round went by, but not the time
+        bbr.on_ack(&path.ack(4_000, MIN_PIPE_CWND * MSS, false));
+        assert!(bbr.probe_rtt_round_done);
+        assert_eq!(bbr.mode, Mode::ProbeRtt);
+
+        path.now = done;
+        bbr.on_ack(&path.ack(4_000, MIN_PIPE_CWND * MSS, false));
+        assert_eq!(bbr.mode, Mode::ProbeBw);
+        assert_eq!(bbr.min_rtt_stamp, path.now);
+        assert!(bbr.cwnd > MIN_PIPE_CWND * MSS);
+    }
+
+    #[test]
+    fn btl_bw_filter_expires() {
+        let mut bbr = Bbr::new(MSS);
+        let mut path = Path::new();
+        bbr.on_ack(&path.ack(100_000, 200_000, false));
+        assert_eq!(bbr.btl_bw(), 10_000_000);
+
+        for _ in 1..BTL_BW_FILTER_LEN {
+            bbr.on_ack(&path.ack(10_000, 200_000, false));
+            assert_eq!(bbr.btl_bw(), 10_000_000);
+        }
+        bbr.on_ack(&path.ack(10_000, 200_000, false));
+        assert_eq!(bbr.btl_bw(), 1_000_000);
+    }
+
+    #[test]
+    fn app_limited_samples_only_raise_btl_bw() {
+        let mut bbr = Bbr::new(MSS);
+        let mut path = Path::new();
+        bbr.on_ack(&path.ack(100_000, 200_000, false));
+        for _ in 0..2 * BTL_BW_FILTER_LEN {
+            bbr.on_ack(&path.ack(10_000, 200_000, true));
+        }
+        assert_eq!(bbr.btl_bw(), 10_000_000);
+
+        bbr.on_ack(&path.ack(200_000, 200_000, true));
+        assert_eq!(bbr.btl_bw(), 20_000_000);
+    }
+
+    #[test]
+    fn loss_updates_the_model_once() {
+        // The acknowledgment moves ProbeBW past its draining phase, and only once
+        let (mut bbr, mut path) = probe_bw();
+        bbr.cycle_index = 1;
+        bbr.pacing_gain = PACING_GAIN_CYCLE[1];
+        let ack = path.ack(40_000, 20_000, false);
+        bbr.on_ack(&ack);
+        bbr.on_loss(&ack);
+        assert_eq!(bbr.cycle_index, 2);
+        assert!(bbr.in_recovery);
+        assert_eq!(bbr.cwnd, 20_000);
+
+        // A duplicate acknowledgment only goes through `on_loss`
+        let mut bbr = Bbr::new(MSS);
+        let mut ack = path.ack(0, 20_000, false);
+        ack.acked = 0;
+        bbr.on_loss(&ack);
+        assert_eq!(bbr.round_count, 1);
+        assert_eq!(bbr.cwnd, 21_000);
+    }
+}
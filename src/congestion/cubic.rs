@@ -0,0 +1,414 @@
+use std::{
+    cmp,
+    time::{Duration, Instant},
+};
+
+use super::{initial_window, Ack, CongestionControl};
+use crate::tcp::is_after;
+
+/// Scaling constant of the cubic function (C)
+const C: f64 = 0.4;
+/// Multiplicative decrease factor (β_cubic)
+const BETA: f64 = 0.7;
+/// Additive increase factor of the Reno-friendly window, until it reaches the window before the
+/// last reduction (α_cubic) (RFC 9438 - Section 4.3)
+const ALPHA: f64 = 3.0 * (1.0 - BETA) / (1.0 + BETA);
+
+/// Segments slow start may grow the window by per acknowledgment, as we don't pace (L)
+const SLOW_START_LIMIT: usize = 8;
+/// Bounds of the RTT increase deemed to be a sign of a building queue
+const MIN_RTT_THRESH: Duration = Duration::from_millis(4);
+const MAX_RTT_THRESH: Duration = Duration::from_millis(16);
+/// Fraction of the last round's minimum RTT an increase is compared to
+const MIN_RTT_DIVISOR: u32 = 8;
+/// RTT samples needed in a round before comparing its minimum RTT to the last round's
+const N_RTT_SAMPLE: usize = 8;
+/// Slow start growth divisor in the conservative slow start
+const CSS_GROWTH_DIVISOR: usize = 4;
+/// Rounds in the conservative slow start before moving to congestion avoidance
+const CSS_ROUNDS: usize = 5;
+
+/// CUBIC congestion control. (RFC 9438)
+///
+/// In congestion avoidance, the window follows a cubic function of the time since the last
+/// reduction, plateauing around the window the loss happened at before probing further. It
+/// never grows slower than Reno would, and slow start ends with HyStart++ (RFC 9406) once the
+/// RTT increases, rather than with a loss.
+#[derive(Debug)]
+pub struct Cubic {
+    cwnd: usize,
+    ssthresh: usize,
+    /// window before the last reduction, in segments (W_max)
+    w_max: f64,
+    /// window before the last reduction, not lowered by fast convergence, in segments
+    /// (cwnd_prior)
+    cwnd_prior: f64,
+    /// time the current congestion avoidance stage started at, if it did (t_epoch)
+    epoch: Option<Instant>,
+    /// time it takes the window to grow back to `w_max`, in seconds (K)
+    k: f64,
+    /// window Reno would have in the current congestion avoidance stage, in segments (W_est)
+    w_est: f64,
+    /// smoothed round-trip time, if it was measured
+    srtt: Option<Duration>,
+    /// HyStart++ state, until the first slow start ends
+    hystart: Option<HyStart>,
+}
+
+impl Cubic {
+    pub fn new(mss: usize) -> Self {
+        Cubic {
+            cwnd: initial_window(mss),
+            ssthresh: usize::MAX,
+            w_max: 0.0,
+            cwnd_prior: 0.0,
+            epoch: None,
+            k: 0.0,
+            w_est: 0.0,
+            srtt: None,
+            hystart: Some(HyStart::default()),
+        }
+    }
+
+    /// Window of the cubic function `t` seconds into the congestion avoidance stage, in segments
+    /// (RFC 9438 - Section 4.2 - Figure 1)
+    fn w_cubic(&self, t: f64) -> f64 {
+        C * (t - self.k).powi(3) + self.w_max
+    }
+
+    fn congestion_avoidance(&mut self, ack: &Ack) {
+        let mss = ack.mss as f64;
+        let cwnd = self.cwnd as f64 / mss;
+        let epoch = match self.epoch {
+            Some(epoch) => epoch,
+            None => {
+                // Start a new congestion avoidance stage (RFC 9438 - Section 4.2 - Figure 2)
+                if cwnd < self.w_max {
+                    self.k = ((self.w_max - cwnd) / C).cbrt();
+                } else {
+                    self.k = 0.0;
+                    self.w_max = cwnd;
+                }
+                self.w_est = cwnd;
+                *self.epoch.insert(ack.now)
+            }
+        };
+
+        // Reno-friendly region (RFC 9438 - Section 4.3)
+        let alpha = if self.w_est >= self.cwnd_prior {
+            1.0
+        } else {
+            ALPHA
+        };
+        self.w_est += alpha * (ack.acked as f64 / mss) / cwnd;
+
+        let t = (ack.now - epoch).as_secs_f64();
+        let rtt = self.srtt.map_or(0.0, |srtt| srtt.as_secs_f64());
+        let target = if self.w_cubic(t) < self.w_est {
+            self.w_est
+        } else {
+            // Concave and convex regions (RFC 9438 - Section 4.4 and 4.5)
+            let target = self.w_cubic(t + rtt).clamp(cwnd, 1.5 * cwnd);
+            cwnd + (target - cwnd) / cwnd * (ack.acked as f64 / mss)
+        };
+        self.cwnd = cmp::max(self.cwnd, (target * mss) as usize);
+    }
+
+    /// Lowers the slow start threshold on a loss, and remembers the window it happened at.
+    /// (RFC 9438 - Section 4.6 and 4.7)
+    fn reduce(&mut self, flight_size: usize, mss: usize) {
+        let cwnd = self.cwnd as f64 / mss as f64;
+        self.epoch = None;
+        self.hystart = None;
+        self.cwnd_prior = cwnd;
+        // Fast convergence, release bandwidth to newer flows when the window keeps shrinking
+        self.w_max = if cwnd < self.w_max {
+            cwnd * (1.0 + BETA) / 2.0
+        } else {
+            cwnd
+        };
+        self.ssthresh = cmp::max((flight_size as f64 * BETA) as usize, 2 * mss);
+    }
+}
+
+impl CongestionControl for Cubic {
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+
+    fn on_ack(&mut self, ack: &Ack) {
+        if let Some(rtt) = ack.rtt {
+            self.srtt = Some(self.srtt.map_or(rtt, |srtt| (srtt * 7 + rtt) / 8));
+        }
+
+        if self.cwnd >= self.ssthresh {
+            self.congestion_avoidance(ack);
+            return;
+        }
+        match self.hystart.as_mut().map(|hystart| hystart.on_ack(ack)) {
+            Some(Some(divisor)) => {
+                // RFC 9406 - Section 4.2
+                self.cwnd += cmp::min(ack.acked, SLOW_START_LIMIT * ack.mss) / divisor;
+            }
+            Some(None) => {
+                // Leave the conservative slow start for congestion avoidance
+                self.hystart = None;
+                self.ssthresh = self.cwnd;
+            }
+            // Slow start after a loss (RFC 5681 - Section 3.1 - Equation 2)
+            None => self.cwnd += cmp::min(ack.acked, ack.mss),
+        }
+    }
+
+    fn on_loss(&mut self, ack: &Ack) {
+        self.reduce(ack.flight_size, ack.mss);
+        self.cwnd = self.ssthresh;
+    }
+
+    fn on_recovery_end(&mut self, _ack: &Ack) {
+        self.cwnd = cmp::max(self.cwnd, self.ssthresh);
+    }
+
+    fn on_rto(&mut self, flight_size: usize, mss: usize) {
+        // The first congestion avoidance stage after a timeout starts with K = 0, from the
+        // window it starts at (RFC 9438 - Section 4.8)
+        self.reduce(flight_size, mss);
+        self.w_max = 0.0;
+        self.cwnd = mss;
+    }
+}
+
+/// HyStart++ slow start. (RFC 9406)
+///
+/// Slow start moves to a conservative slow start, growing four times slower, once the minimum
+/// RTT of a round increases. It goes back to slow start if the increase was spurious, and
+/// otherwise leaves for congestion avoidance a few rounds later.
+#[derive(Debug, Default)]
+struct HyStart {
+    /// `SND.NXT` when the current round started, it ends once it is acknowledged (windowEnd)
+    window_end: Option<u32>,
+    /// minimum RTT of the last round (lastRoundMinRTT)
+    last_round_min_rtt: Option<Duration>,
+    /// minimum RTT of the current round (currentRoundMinRTT)
+    current_round_min_rtt: Option<Duration>,
+    /// RTT samples in the current round (rttSampleCount)
+    rtt_sample_count: usize,
+    /// minimum RTT when the conservative slow start started, while in it (cssBaselineMinRtt)
+    css_baseline_min_rtt: Option<Duration>,
+    /// rounds spent in the conservative slow start
+    css_rounds: usize,
+}
+
+impl HyStart {
+    /// Tracks the rounds and RTTs of an acknowledgment, returning the divisor of the slow start
+    /// growth, or `None` once slow start should end. (RFC 9406 - Section 4.2)
+    fn on_ack(&mut self, ack: &Ack) -> Option<usize> {
+        if self
+            .window_end
+            .is_none_or(|window_end| !is_after(window_end, ack.una))
+        {
+            self.window_end = Some(ack.nxt);
+            self.last_round_min_rtt = self.current_round_min_rtt.take();
+            self.rtt_sample_count = 0;
+            if self.css_baseline_min_rtt.is_some() {
+                self.css_rounds += 1;
+                if self.css_rounds >= CSS_ROUNDS {
+                    return None;
+                }
+            }
+        }
+
+        if let Some(rtt) = ack.rtt {
+            self.current_round_min_rtt = Some(
+                self.current_round_min_rtt
+                    .map_or(rtt, |min_rtt| cmp::min(min_rtt, rtt)),
+            );
+            self.rtt_sample_count += 1;
+        }
+
+        if self.rtt_sample_count >= N_RTT_SAMPLE {
+            if let (Some(current), Some(last)) =
+                (self.current_round_min_rtt, self.last_round_min_rtt)
+            {
+                match self.css_baseline_min_rtt {
+                    None => {
+                        let rtt_thresh =
+                            (last / MIN_RTT_DIVISOR).clamp(MIN_RTT_THRESH, MAX_RTT_THRESH);
+                        if current >= last + rtt_thresh {
+                            self.css_baseline_min_rtt = Some(current);
+                            self.css_rounds = 0;
+                        }
+                    }
+                    // The RTT increase was spurious, resume slow start
+                    Some(baseline) if current < baseline => self.css_baseline_min_rtt = None,
+                    Some(_) => {}
+                }
+            }
+        }
+
+        Some(if self.css_baseline_min_rtt.is_some() {
+            CSS_GROWTH_DIVISOR
+        } else {
+            1
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: usize = 1000;
+
+    fn ack(now: Instant, una: u32, nxt: u32, rtt: Duration) -> Ack {
+        Ack {
+            now,
+            acked: MSS,
+            flight_size: nxt.wrapping_sub(una) as usize,
+            mss: MSS,
+            rtt: Some(rtt),
+            una,
+            nxt,
+            delivered: 0,
+            rate_sample: None,
+        }
+    }
+
+    /// Acknowledges a round of `N_RTT_SAMPLE` segments sent from `start`, returning the growth
+    /// divisor of its last acknowledgment, or `None` as soon as slow start ends
+    fn round(hystart: &mut HyStart, start: u32, rtt: Duration) -> Option<usize> {
+        let nxt = start.wrapping_add(N_RTT_SAMPLE as u32);
+        let mut divisor = None;
+        for i in 0..N_RTT_SAMPLE as u32 {
+            divisor =
+                Some(hystart.on_ack(&ack(Instant::now(), start.wrapping_add(i), nxt, rtt))?);
+        }
+        divisor
+    }
+
+    /// Starts of the consecutive rounds, wrapping around the sequence numbers
+    fn rounds() -> impl Iterator<Item = u32> {
+        (0..).map(|round: u32| (u32::MAX - 20).wrapping_add(round * N_RTT_SAMPLE as u32))
+    }
+
+    #[test]
+    fn hystart_leaves_slow_start_after_css_rounds() {
+        let mut hystart = HyStart::default();
+        let mut rounds = rounds();
+        assert_eq!(
+            round(
+                &mut hystart,
+                rounds.next().unwrap(),
+                Duration::from_millis(10)
+            ),
+            Some(1)
+        );
+        // 4ms over the last round's minimum RTT is an increase
+        let rtt = Duration::from_millis(14);
+        assert_eq!(
+            round(&mut hystart, rounds.next().unwrap(), rtt),
+            Some(CSS_GROWTH_DIVISOR)
+        );
+        for _ in 1..CSS_ROUNDS {
+            assert_eq!(
+                round(&mut hystart, rounds.next().unwrap(), rtt),
+                Some(CSS_GROWTH_DIVISOR)
+            );
+        }
+        assert_eq!(round(&mut hystart, rounds.next().unwrap(), rtt), None);
+    }
+
+    #[test]
+    fn hystart_ignores_small_and_spurious_increases() {
+        let mut hystart = HyStart::default();
+        let mut rounds = rounds();
+        assert_eq!(
+            round(
+                &mut hystart,
+                rounds.next().unwrap(),
+                Duration::from_millis(10)
+            ),
+            Some(1)
+        );
+        let rtt = Duration::from_millis(13);
+        assert_eq!(round(&mut hystart, rounds.next().unwrap(), rtt), Some(1));
+
+        let rtt = Duration::from_millis(20);
+        assert_eq!(
+            round(&mut hystart, rounds.next().unwrap(), rtt),
+            Some(CSS_GROWTH_DIVISOR)
+        );
+        let rtt = Duration::from_millis(15);
+        assert_eq!(round(&mut hystart, rounds.next().unwrap(), rtt), Some(1));
+    }
+
+    #[test]
+    fn loss_sets_k_and_reno_friendly_window() {
+        let now = Instant::now();
+        let mut cubic = Cubic::new(MSS);
+        cubic.cwnd = 100 * MSS;
+        cubic.on_loss(&ack(now, 0, 100 * MSS as u32, Duration::from_millis(10)));
+        assert_eq!(cubic.cwnd, 70 * MSS);
+        assert_eq!(cubic.ssthresh, 70 * MSS);
+        assert!(cubic.hystart.is_none());
+
+        // A window of acknowledgments at the start of the congestion avoidance stage
+        for i in 0..70 {
+            cubic.on_ack(&ack(
+                now,
+                i * MSS as u32,
+                70 * MSS as u32,
+                Duration::from_millis(10),
+            ));
+        }
+        assert!((cubic.k - (30.0 / C).cbrt()).abs() < 1e-9);
+        assert!((cubic.w_est - 70.0 - ALPHA).abs() < 0.01);
+        assert_eq!(cubic.cwnd, (cubic.w_est * MSS as f64) as usize);
+    }
+
+    #[test]
+    fn fast_convergence_lowers_w_max() {
+        let now = Instant::now();
+        let mut cubic = Cubic::new(MSS);
+        cubic.cwnd = 100 * MSS;
+        cubic.on_loss(&ack(now, 0, 100 * MSS as u32, Duration::from_millis(10)));
+        assert_eq!(cubic.w_max, 100.0);
+        cubic.on_loss(&ack(now, 0, 70 * MSS as u32, Duration::from_millis(10)));
+        assert_eq!(cubic.w_max, 70.0 * (1.0 + BETA) / 2.0);
+        assert_eq!(cubic.cwnd, 49 * MSS);
+    }
+
+    #[test]
+    fn timeout_restarts_from_one_segment() {
+        let now = Instant::now();
+        let mut cubic = Cubic::new(MSS);
+        cubic.cwnd = 100 * MSS;
+        cubic.on_rto(100 * MSS, MSS);
+        assert_eq!(cubic.cwnd, MSS);
+        assert_eq!(cubic.ssthresh, 70 * MSS);
+
+        // Slow start up to the threshold, then a congestion avoidance stage with K = 0
+        let mut una = 0;
+        while cubic.cwnd < cubic.ssthresh {
+            una += MSS as u32;
+            cubic.on_ack(&ack(
+                now,
+                una,
+                una + 100 * MSS as u32,
+                Duration::from_millis(10),
+            ));
+        }
+        cubic.on_ack(&ack(
+            now,
+            una,
+            una + 100 * MSS as u32,
+            Duration::from_millis(10),
+        ));
+        assert_eq!(cubic.k, 0.0);
+        assert_eq!(cubic.w_max, 70.0);
+    }
+}
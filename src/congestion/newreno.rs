@@ -0,0 +1,59 @@
+use std::cmp;
+
+use super::{initial_window, Ack, CongestionControl};
+
+/// NewReno congestion control. (RFC 5681 and RFC 6582)
+///
+/// The window grows by an MSS per acknowledgment in slow start, and by an MSS per round trip in
+/// congestion avoidance. A loss halves it, and a retransmission timeout shrinks it to one segment.
+#[derive(Debug)]
+pub struct NewReno {
+    cwnd: usize,
+    ssthresh: usize,
+}
+
+impl NewReno {
+    pub fn new(mss: usize) -> Self {
+        NewReno {
+            cwnd: initial_window(mss),
+            ssthresh: usize::MAX,
+        }
+    }
+}
+
+impl CongestionControl for NewReno {
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+
+    fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+
+    fn on_ack(&mut self, ack: &Ack) {
+        if self.cwnd < self.ssthresh {
+            // Slow start (RFC 5681 - Section 3.1 - Equation 2)
+            self.cwnd += cmp::min(ack.acked, ack.mss);
+        } else {
+            // Congestion avoidance (RFC 5681 - Section 3.1 - Equation 3)
+            self.cwnd += cmp::max(ack.mss * ack.mss / self.cwnd, 1);
+        }
+    }
+
+    fn on_loss(&mut self, ack: &Ack) {
+        // RFC 5681 - Section 3.1 - Equation 4
+        self.ssthresh = cmp::max(ack.flight_size / 2, 2 * ack.mss);
+        self.cwnd = self.ssthresh;
+    }
+
+    fn on_recovery_end(&mut self, _ack: &Ack) {
+        // Deflate the window (RFC 6582 - Section 3.2 - Step 3)
+        self.cwnd = self.ssthresh;
+    }
+
+    fn on_rto(&mut self, flight_size: usize, mss: usize) {
+        // The loss window is a single segment (RFC 5681 - Section 3.1)
+        self.ssthresh = cmp::max(flight_size / 2, 2 * mss);
+        self.cwnd = mss;
+    }
+}
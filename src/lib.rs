@@ -1,8 +1,19 @@
+mod config;
+mod congestion;
 mod tcp;
 
+pub use config::{TcpConfig, TcpConfigBuilder};
+pub use congestion::{
+    Ack, Bbr, CongestionControl, CongestionControlFactory, Cubic, NewReno, RateSample,
+};
+
 use etherparse::{IpNumber, Ipv4HeaderSlice, TcpHeaderSlice};
 use std::{
-    collections::{hash_map::Entry, HashMap, VecDeque},
+    collections::{
+        hash_map::{Entry, RandomState},
+        HashMap, VecDeque,
+    },
+    hash::BuildHasher,
     io::{
         self,
         prelude::{Read, Write},
@@ -11,6 +22,7 @@ use std::{
     os::fd::BorrowedFd,
     sync::{Arc, Condvar, Mutex, OnceLock},
     thread,
+    time::{Duration, Instant},
 };
 
 // TODO: CHANGEME
@@ -22,14 +34,18 @@ struct Quad {
     remote: (Ipv4Addr, u16),
 }
 
-#[derive(Default, Debug)]
+#[derive(Debug)]
 struct ConnectionManager {
     terminate: bool,
     connections: HashMap<Quad, tcp::Connection>,
     pending: HashMap<u16, VecDeque<Quad>>,
+    /// configuration of the stack
+    config: TcpConfig,
+    /// initial sequence numbers generator
+    isn: tcp::IsnGenerator,
 }
 
-#[derive(Default, Debug)]
+#[derive(Debug)]
 struct ConnHandler {
     conn_manager: Mutex<ConnectionManager>,
 
@@ -42,6 +58,55 @@ struct ConnHandler {
 
 type ConnectionHandler = Arc<ConnHandler>;
 
+impl ConnectionManager {
+    fn new(config: TcpConfig) -> Self {
+        let isn = match config.isn_seed {
+            Some(seed) => tcp::IsnGenerator::with_seed(seed),
+            None => tcp::IsnGenerator::default(),
+        };
+        ConnectionManager {
+            terminate: false,
+            connections: HashMap::new(),
+            pending: HashMap::new(),
+            config,
+            isn,
+        }
+    }
+
+    /// Deletes the connection of `quad`, along with its pending accept if any
+    fn remove(&mut self, quad: &Quad) -> Option<tcp::Connection> {
+        if let Some(pending) = self.pending.get_mut(&quad.local.1) {
+            pending.retain(|pending| pending != quad);
+        }
+        self.connections.remove(quad)
+    }
+
+    /// Checks whether a listener or a connection uses the local `port`
+    fn is_port_in_use(&self, port: u16) -> bool {
+        self.pending.contains_key(&port) || self.connections.keys().any(|quad| quad.local.1 == port)
+    }
+
+    /// Picks a free local port from the ephemeral range, looking for one from a random offset so
+    /// that the ports are hard to guess. (RFC 6056 - Section 3.3.1)
+    fn ephemeral_port(&self) -> io::Result<u16> {
+        let ports = &self.config.ephemeral_ports;
+        let start = *ports.start();
+        let count = u32::from(*ports.end() - start) + 1;
+        let offset = (RandomState::new().hash_one(Instant::now()) % u64::from(count)) as u32;
+        for i in 0..count {
+            let port = start + ((offset + i) % count) as u16;
+            if !self.is_port_in_use(port) {
+                return Ok(port);
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "no ephemeral port available!",
+        ))
+    }
+}
+
 pub struct Tcp {
     /// Conection handler
     conn_handler: Option<ConnectionHandler>,
@@ -67,9 +132,35 @@ impl Drop for Tcp {
     }
 }
 
+impl ConnHandler {
+    fn new(config: TcpConfig) -> Self {
+        ConnHandler {
+            conn_manager: Mutex::new(ConnectionManager::new(config)),
+            pending_cvar: Condvar::new(),
+            receive_cvar: Condvar::new(),
+            send_cvar: Condvar::new(),
+            estab_cvar: Condvar::new(),
+        }
+    }
+
+    /// Wakes up the users blocked on whatever the connection made available
+    fn notify(&self, available: tcp::Available) {
+        if available.contains(tcp::Available::READ) {
+            self.receive_cvar.notify_all();
+        }
+        if available.contains(tcp::Available::WRITE) {
+            self.send_cvar.notify_all();
+        }
+        if available.contains(tcp::Available::ESTAB) {
+            self.estab_cvar.notify_all();
+        }
+    }
+}
+
 fn packet_loop(conn_handler: ConnectionHandler) -> io::Result<()> {
-    let mut buf = [0u8; 1500];
-    let nic = NIC::get_mut_ref()?;
+    let mtu = conn_handler.conn_manager.lock().unwrap().config.mtu;
+    let mut buf = vec![0u8; mtu];
+    let nic = Nic::get_mut_ref()?;
     loop {
         use nix::poll;
         use std::os::unix::io::AsRawFd;
@@ -81,13 +172,40 @@ fn packet_loop(conn_handler: ConnectionHandler) -> io::Result<()> {
         };
         let n = poll::poll(&mut pfd[..], poll::PollTimeout::from(10u8))?;
         assert_ne!(n, -1);
-        // Read from the tunnel nic
-        // TODO: Set timeout for the recv
-        let len = nic.lock().unwrap().recv(&mut buf)?;
 
         // TODO: if conn_manager.terminate && Arc get_strong_refs(conn_manager) == 1; then tear
         // down all connections and return.
 
+        // Drive the timers and transmit the data queued by the users
+        let mut cm = conn_handler.conn_manager.lock().unwrap();
+        let mut available = tcp::Available::empty();
+        for connection in cm.connections.values_mut() {
+            available |= connection.on_tick()?;
+        }
+        let closed: Vec<Quad> = cm
+            .connections
+            .iter()
+            .filter(|(_, connection)| connection.is_closed())
+            .map(|(quad, _)| *quad)
+            .collect();
+        for quad in &closed {
+            cm.remove(quad);
+        }
+        if !closed.is_empty() {
+            // Wake everyone up so that the users of the removed connections notice
+            available = tcp::Available::all();
+        }
+        drop(cm);
+        conn_handler.notify(available);
+
+        // Nothing to read from the tunnel nic
+        if n == 0 {
+            continue;
+        }
+
+        // Read from the tunnel nic
+        let len = nic.lock().unwrap().recv(&mut buf)?;
+
         let mut offset = 0;
         // Parse IPv4 packet
         let iphdr = match Ipv4HeaderSlice::from_slice(&buf[offset..len]) {
@@ -113,75 +231,79 @@ fn packet_loop(conn_handler: ConnectionHandler) -> io::Result<()> {
 
         let mut cm_lock = conn_handler.conn_manager.lock().unwrap();
         let cm = &mut *cm_lock;
+        // The nic routes the whole subnet to us, but only our own address may answer
+        if iphdr.destination_addr() != cm.config.address {
+            continue;
+        }
         let quad = Quad {
             local: (iphdr.destination_addr(), tcphdr.destination_port()),
             remote: (iphdr.source_addr(), tcphdr.source_port()),
         };
         match cm.connections.entry(quad) {
             Entry::Occupied(mut connection) => {
-                let available = connection.get_mut().on_packet(&tcphdr, &buf[offset..len])?;
+                let available =
+                    connection
+                        .get_mut()
+                        .on_packet(&tcphdr, iphdr.ecn(), &buf[offset..len])?;
 
                 // remove the connection from the connections map if closed
                 if connection.get().is_closed() {
-                    connection.remove();
+                    cm.remove(&quad);
+                    drop(cm_lock);
+                    conn_handler.notify(tcp::Available::all());
                     continue;
                 }
 
                 // TODO: compare before/after and do the following only if they differ
                 drop(cm_lock);
-                if available.contains(tcp::Available::READ) {
-                    conn_handler.receive_cvar.notify_all();
-                }
-                if available.contains(tcp::Available::WRITE) {
-                    // TODO: do something similar to the receive_cvar
-                }
+                conn_handler.notify(available);
             }
             Entry::Vacant(entry) => {
                 if let Some(pending) = cm.pending.get_mut(&tcphdr.destination_port()) {
-                    if let Some(connection) = tcp::Connection::accept(&iphdr, &tcphdr)? {
+                    let iss = cm.isn.generate(&quad);
+                    if let Some(connection) =
+                        tcp::Connection::accept(&iphdr, &tcphdr, iss, &cm.config)?
+                    {
                         entry.insert(connection);
                         pending.push_front(quad);
                         drop(cm_lock);
                         conn_handler.pending_cvar.notify_all();
                     }
+                } else {
+                    // Nothing is listening on the port, refuse the connection
+                    tcp::send_rst(&iphdr, &tcphdr, len - offset)?;
                 }
             }
         }
     }
 }
 
-struct NIC;
+struct Nic;
 
-impl NIC {
-    fn init() -> io::Result<()> {
-        let nic = tun_tap::Iface::without_packet_info("tun0", tun_tap::Mode::Tun)?;
-        unsafe {
-            NIC_ONCE_LOCK
-                .set(Arc::new(Mutex::new(nic)))
-                .expect("failed to set NIC");
-        }
-        Ok(())
+impl Nic {
+    fn init(interface: &str) -> io::Result<()> {
+        let nic = tun_tap::Iface::without_packet_info(interface, tun_tap::Mode::Tun)?;
+        NIC_ONCE_LOCK
+            .set(Arc::new(Mutex::new(nic)))
+            .map_err(|_| io::Error::new(io::ErrorKind::AlreadyExists, "NIC already initialized!"))
     }
 
     fn get_mut_ref() -> io::Result<Arc<Mutex<tun_tap::Iface>>> {
-        let nic;
-        unsafe {
-            nic = NIC_ONCE_LOCK
-                .get_mut()
-                .expect("failed to get mutable reference to the NIC")
-                .clone();
-        }
+        let nic = NIC_ONCE_LOCK
+            .get()
+            .expect("failed to get mutable reference to the NIC")
+            .clone();
         Ok(nic)
     }
 }
 
-pub(crate) static mut NIC_ONCE_LOCK: OnceLock<Arc<Mutex<tun_tap::Iface>>> = OnceLock::new();
+pub(crate) static NIC_ONCE_LOCK: OnceLock<Arc<Mutex<tun_tap::Iface>>> = OnceLock::new();
 
 impl Tcp {
-    /// Creates a new NIC and initializes the connection manager state
-    pub fn init() -> io::Result<Self> {
-        NIC::init()?;
-        let conn_handler: ConnectionHandler = Arc::default();
+    /// Attaches to the TUN interface of `config` and initializes the connection manager state
+    pub fn init(config: TcpConfig) -> io::Result<Self> {
+        Nic::init(&config.interface)?;
+        let conn_handler = Arc::new(ConnHandler::new(config));
         let join_handler = {
             let cm = conn_handler.clone();
             thread::spawn(move || packet_loop(cm))
@@ -221,21 +343,57 @@ impl Tcp {
         })
     }
 
-    /// Connects to a remote host
+    /// Gets the configuration of the stack
+    pub fn config(&self) -> TcpConfig {
+        self.conn_handler
+            .as_ref()
+            .unwrap()
+            .conn_manager
+            .lock()
+            .unwrap()
+            .config
+            .clone()
+    }
+
+    /// Connects to a remote host from a free ephemeral port
     pub fn connect(&mut self, addr: SocketAddrV4) -> io::Result<TcpStream> {
-        let my_ip: Ipv4Addr = std::env::var("MY_IP")
+        let port = self
+            .conn_handler
+            .as_mut()
+            .unwrap()
+            .conn_manager
+            .lock()
             .unwrap()
-            .parse()
-            .expect("local host doesn't have a valid IP");
+            .ephemeral_port()?;
+        self.connect_from(port, addr)
+    }
+
+    /// Connects to a remote host from the given local port
+    pub fn connect_from(&mut self, port: u16, addr: SocketAddrV4) -> io::Result<TcpStream> {
+        if port == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid local port!",
+            ));
+        }
+
+        let conn_handler = self.conn_handler.as_mut().unwrap().clone();
+        let mut cm = conn_handler.conn_manager.lock().unwrap();
         let quad = Quad {
-            local: (my_ip, 9182u16),
+            local: (cm.config.address, port),
             remote: (addr.ip().to_owned(), addr.port()),
         };
-        let connection = tcp::Connection::establish_connection(quad.remote.0, quad.remote.1)?;
-        let conn_handler = self.conn_handler.as_mut().unwrap().clone();
-        let mut cm = conn_handler.conn_manager.lock().unwrap();
+        if cm.pending.contains_key(&port) || cm.connections.contains_key(&quad) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "address already in use!",
+            ));
+        }
 
-        assert!(cm.connections.insert(quad, connection).is_none());
+        let iss = cm.isn.generate(&quad);
+        let mut connection = tcp::Connection::establish_connection(&quad, iss, &cm.config)?;
+        connection.attach();
+        cm.connections.insert(quad, connection);
 
         loop {
             let connection = cm.connections.get(&quad).ok_or_else(|| {
@@ -245,8 +403,12 @@ impl Tcp {
                 )
             })?;
 
+            if let Some(error) = connection.error() {
+                cm.connections.remove(&quad);
+                return Err(error.into());
+            }
+
             if connection.is_established() {
-                println!("finally");
                 return Ok(TcpStream {
                     quad,
                     conn_handler: self.conn_handler.as_ref().unwrap().clone(),
@@ -273,10 +435,10 @@ impl Drop for TcpListener {
             .expect("port closed while listener is active!");
 
         for quad in pending {
-            // TODO: terminate connection
-            // cm.connections.get_mut(&quad)
-
-            unimplemented!()
+            if let Some(mut connection) = cm.connections.remove(&quad) {
+                // Nothing can be reported from a drop, the peer times out if the RST is lost
+                let _ = connection.abort();
+            }
         }
     }
 }
@@ -290,6 +452,9 @@ impl TcpListener {
                 .expect("port closed while listener is active!")
                 .pop_back()
             {
+                if let Some(connection) = cm.connections.get_mut(&quad) {
+                    connection.attach();
+                }
                 return Ok(TcpStream {
                     quad,
                     conn_handler: self.conn_handler.clone(),
@@ -316,6 +481,10 @@ impl Read for TcpStream {
                 )
             })?;
 
+            if let Some(error) = connection.error() {
+                return Err(error.into());
+            }
+
             if connection.inbuf.is_empty() && connection.is_recv_closed() {
                 // no more data to read, close stream
                 return Ok(0);
@@ -326,15 +495,7 @@ impl Read for TcpStream {
 
                 // TODO: detect FIN and return nread 0
 
-                let (head, tail) = connection.inbuf.as_slices();
-                let mut nread = std::cmp::min(head.len(), buf.len());
-                buf[..nread].copy_from_slice(&head[..nread]);
-                let tread = std::cmp::min(buf.len() - nread, tail.len());
-                buf[nread..nread + tread].copy_from_slice(&head[..tread]);
-                nread += tread;
-                drop(connection.inbuf.drain(..nread));
-
-                return Ok(nread);
+                return connection.read(buf);
             }
 
             cm = self.conn_handler.receive_cvar.wait(cm).unwrap();
@@ -344,65 +505,215 @@ impl Read for TcpStream {
 
 impl Write for TcpStream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.queue(buf, false)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
         let mut cm = self.conn_handler.conn_manager.lock().unwrap();
-        while cm
-            .connections
-            .get(&self.quad)
-            .ok_or_else(|| {
+        loop {
+            let connection = cm.connections.get(&self.quad).ok_or_else(|| {
                 io::Error::new(
                     io::ErrorKind::ConnectionAborted,
                     "stream terminated unexpectedly!",
                 )
-            })?
-            .outbuf
-            .len()
-            >= TRANSMISSION_QLEN_SIZE
-        {
-            cm = self.conn_handler.send_cvar.wait(cm).unwrap();
-        }
+            })?;
 
-        let connection = cm.connections.get_mut(&self.quad).ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::ConnectionAborted,
-                "stream terminated unexpectedly!",
-            )
-        })?;
-        let nwrite = std::cmp::min(buf.len(), TRANSMISSION_QLEN_SIZE - connection.outbuf.len());
-        connection.outbuf.extend(&buf[..nwrite]);
+            if let Some(error) = connection.error() {
+                return Err(error.into());
+            }
+
+            if connection.outbuf.is_empty() {
+                return Ok(());
+            }
 
-        Ok(nwrite)
+            cm = self.conn_handler.send_cvar.wait(cm).unwrap();
+        }
     }
+}
 
-    fn flush(&mut self) -> io::Result<()> {
+impl TcpStream {
+    /// Queues as much of `buf` as fits in the outbuf, waiting for room if there is none. Urgent
+    /// data moves the urgent pointer past it.
+    fn queue(&mut self, buf: &[u8], urgent: bool) -> io::Result<usize> {
         let mut cm = self.conn_handler.conn_manager.lock().unwrap();
         loop {
-            let connection = cm.connections.get(&self.quad).ok_or_else(|| {
+            let connection = cm.connections.get_mut(&self.quad).ok_or_else(|| {
                 io::Error::new(
                     io::ErrorKind::ConnectionAborted,
                     "stream terminated unexpectedly!",
                 )
             })?;
 
-            if connection.outbuf.is_empty() {
-                return Ok(());
+            if let Some(error) = connection.error() {
+                return Err(error.into());
+            }
+
+            if connection.is_send_closed() {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "stream is shut down for writing",
+                ));
+            }
+
+            if connection.outbuf.len() < TRANSMISSION_QLEN_SIZE {
+                let nwrite =
+                    std::cmp::min(buf.len(), TRANSMISSION_QLEN_SIZE - connection.outbuf.len());
+                connection.outbuf.extend(&buf[..nwrite]);
+                if urgent {
+                    connection.mark_urgent();
+                }
+                // Send what the windows allow right away rather than on the next tick, which
+                // tries again if the nic fails as the data is queued either way
+                let _ = connection.transmit();
+
+                return Ok(nwrite);
             }
 
             cm = self.conn_handler.send_cvar.wait(cm).unwrap();
         }
     }
-}
 
-impl TcpStream {
+    /// Writes urgent data, which the peer is told about ahead of reading it, e.g. to interrupt
+    /// a telnet-style session. (RFC 9293 - Section 3.8.5)
+    ///
+    /// The data is sent in line, and the urgent pointer marks where it ends.
+    pub fn send_urgent(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.queue(buf, true)
+    }
+
+    /// Gets the number of bytes `read` returns before the end of the urgent data the peer sent,
+    /// if some of it arrived and is unread. `read` never reads past the urgent mark, so that it
+    /// can be told apart from the data after it.
+    pub fn urgent_mark(&self) -> io::Result<Option<usize>> {
+        self.with_connection(|connection| connection.urgent_mark())
+    }
+
+    /// Reads the urgent data the peer sent, up to the urgent mark. Unlike `read`, it doesn't
+    /// block, and returns 0 when no urgent data is pending.
+    pub fn read_urgent(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.with_connection(|connection| {
+            if connection.urgent_mark().is_some() {
+                connection.read(buf)
+            } else {
+                Ok(0)
+            }
+        })?
+    }
+
+    /// Runs `f` on the connection of the stream
+    fn with_connection<T>(&self, f: impl FnOnce(&mut tcp::Connection) -> T) -> io::Result<T> {
+        let mut cm = self.conn_handler.conn_manager.lock().unwrap();
+        let connection = cm.connections.get_mut(&self.quad).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "stream terminated unexpectedly!",
+            )
+        })?;
+        Ok(f(connection))
+    }
+
+    /// Sets the number of times an unacknowledged segment is retransmitted before the connection
+    /// is aborted.
+    pub fn set_retransmission_limit(&self, limit: u32) -> io::Result<()> {
+        self.with_connection(|connection| connection.set_retransmission_limit(limit))
+    }
+
+    /// Gets the number of times an unacknowledged segment is retransmitted before the connection
+    /// is aborted.
+    pub fn retransmission_limit(&self) -> io::Result<u32> {
+        self.with_connection(|connection| connection.retransmission_limit())
+    }
+
+    /// Enables keepalives: once nothing was received for `idle`, a probe is sent every `interval`
+    /// until the peer answers, and the connection is aborted with `TimedOut` once `retries`
+    /// probes go unanswered.
+    pub fn set_keepalive(
+        &self,
+        idle: Duration,
+        interval: Duration,
+        retries: u32,
+    ) -> io::Result<()> {
+        if idle.is_zero() || interval.is_zero() || retries == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid keepalive settings!",
+            ));
+        }
+        let keepalive = tcp::Keepalive {
+            idle,
+            interval,
+            retries,
+        };
+        self.with_connection(|connection| connection.set_keepalive(Some(keepalive)))
+    }
+
+    /// Disables keepalives, which is the default.
+    pub fn disable_keepalive(&self) -> io::Result<()> {
+        self.with_connection(|connection| connection.set_keepalive(None))
+    }
+
+    /// Gets the keepalive idle time, interval and retries, if keepalives are enabled.
+    pub fn keepalive(&self) -> io::Result<Option<(Duration, Duration, u32)>> {
+        self.with_connection(|connection| {
+            connection
+                .keepalive()
+                .map(|keepalive| (keepalive.idle, keepalive.interval, keepalive.retries))
+        })
+    }
+
+    /// Sets the value of the `TCP_NODELAY` option, disabling Nagle's algorithm when `true` so
+    /// that small writes are sent right away rather than coalesced.
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.with_connection(|connection| connection.set_nodelay(nodelay))
+    }
+
+    /// Gets the value of the `TCP_NODELAY` option.
+    pub fn nodelay(&self) -> io::Result<bool> {
+        self.with_connection(|connection| connection.nodelay())
+    }
+
+    /// Replaces the congestion control of the stream, which starts over from its initial window.
+    pub fn set_congestion_control(
+        &self,
+        congestion_control: CongestionControlFactory,
+    ) -> io::Result<()> {
+        self.with_connection(|connection| connection.set_congestion_control(congestion_control))
+    }
+
+    /// Shuts down the read, write, or both halves of the stream.
+    ///
+    /// Shutting down the write half sends a FIN after the data that is already queued, while the
+    /// stream keeps reading until the peer's FIN. Shutting down the read half discards further
+    /// inbound data and makes `read` return 0.
     pub fn shutdown(&self, how: std::net::Shutdown) -> io::Result<()> {
-        // TODO: send a FIN
-        unimplemented!()
+        self.with_connection(|connection| {
+            if let std::net::Shutdown::Read | std::net::Shutdown::Both = how {
+                connection.shutdown_read();
+            }
+            if let std::net::Shutdown::Write | std::net::Shutdown::Both = how {
+                connection.close();
+            }
+        })
     }
 }
 
 impl Drop for TcpStream {
     fn drop(&mut self) {
-        let mut _cm = self.conn_handler.conn_manager.lock().unwrap();
-        // TODO: send a FIN
-        // TODO: _eventually_ remove the self.quad's connection from cm.connections
+        let mut cm = self.conn_handler.conn_manager.lock().unwrap();
+        if let Some(connection) = cm.connections.get_mut(&self.quad) {
+            if connection.has_unread() {
+                // Data would be lost, so tell the peer with a RST (RFC 9293 - Section 3.10.4)
+                let _ = connection.abort();
+            } else {
+                // Send a FIN and discard whatever arrives afterwards, the packet loop deletes the
+                // connection once it is closed
+                connection.close();
+                connection.shutdown_read();
+            }
+            connection.detach();
+            if connection.is_closed() {
+                cm.connections.remove(&self.quad);
+            }
+        }
     }
 }
@@ -1,13 +1,18 @@
-use ruts_tcp::Tcp;
+use ruts_tcp::{Tcp, TcpConfig};
 use std::{
     io::{self, Read, Write},
-    net::SocketAddrV4,
+    net::{Ipv4Addr, SocketAddrV4},
     thread::sleep,
     time::Duration,
 };
 
 fn main() -> io::Result<()> {
-    let mut tcp = Tcp::init()?;
+    let config = TcpConfig::builder()
+        .interface("tun0")
+        .address(Ipv4Addr::new(192, 168, 1, 1), 24)
+        .mtu(1500)
+        .build()?;
+    let mut tcp = Tcp::init(config)?;
 
     // Server
     let mut listener = tcp.bind(8080)?;
@@ -37,8 +42,9 @@ fn main() -> io::Result<()> {
         {
             println!("Now running");
             client
-                .write(String::from("Hello, world!").as_bytes())
+                .write_all(String::from("Hello, world!").as_bytes())
                 .unwrap();
+            client.flush().unwrap();
 
             let mut buf = [0; 1024];
             let len = client.read(&mut buf).unwrap();
@@ -1,18 +1,67 @@
 use bitflags::bitflags;
-use etherparse::{IpNumber, Ipv4Header, Ipv4HeaderSlice, TcpHeader, TcpHeaderSlice};
+use etherparse::{
+    IpNumber, Ipv4Ecn, Ipv4Header, Ipv4HeaderSlice, TcpHeader, TcpHeaderSlice, TcpOptionElement,
+};
 use std::{
-    cmp::Ordering,
-    collections::VecDeque,
+    cmp::{self, Ordering},
+    collections::{BTreeMap, VecDeque},
     io::{self, Write},
-    net::Ipv4Addr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    congestion::{Ack, CongestionControl, CongestionControlFactory},
+    Nic, Quad, TcpConfig, TRANSMISSION_QLEN_SIZE,
 };
 
-use crate::NIC;
+mod ecn;
+mod isn;
+mod rate;
+mod reassembly;
+mod scoreboard;
+mod timestamps;
+
+use ecn::{Ecn, ECT0};
+pub(crate) use isn::IsnGenerator;
+use rate::DeliveryRate;
+use reassembly::Reassembly;
+use scoreboard::{Scoreboard, DUP_THRESH};
+use timestamps::{Timestamps, TIMESTAMPS_LEN};
+
+/// Maximum segment size assumed when the peer doesn't send the MSS option. (RFC 9293 - Section
+/// 3.7.1)
+const DEFAULT_MSS: usize = 536;
+/// Size of the IPv4 and TCP headers without options, which the MSS doesn't account for
+const HEADERS_LEN: usize = Ipv4Header::MIN_LEN + TcpHeader::MIN_LEN;
+/// Largest window scale shift count. (RFC 7323 - Section 2.3)
+pub(crate) const MAX_WINDOW_SHIFT: u8 = 14;
+
+/// Retransmission timeout used until the first RTT measurement. (RFC 6298 - Section 2.1)
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+/// Lower bound of the retransmission timeout. (RFC 6298 - Section 2.4)
+const MIN_RTO: Duration = Duration::from_secs(1);
+/// Upper bound of the retransmission timeout. (RFC 6298 - Section 2.5)
+const MAX_RTO: Duration = Duration::from_secs(60);
+/// Clock granularity, i.e. the interval at which the packet loop ticks the connections
+const CLOCK_GRANULARITY: Duration = Duration::from_millis(10);
+/// Number of retransmissions of a segment after which the connection is aborted
+pub(crate) const DEFAULT_RETRANSMISSION_LIMIT: u32 = 15;
+/// Maximum segment lifetime, TIME-WAIT lasts for twice as long. (RFC 9293 arbitrarily suggests 2
+/// minutes, this is what Linux uses)
+pub(crate) const DEFAULT_MSL: Duration = Duration::from_secs(30);
+/// Upper bound of the maximum segment lifetime, keeping the TIME-WAIT and FIN-WAIT-2 deadlines
+/// within the range of the clock
+pub(crate) const MAX_MSL: Duration = Duration::from_secs(60 * 60);
+/// Time an acknowledgment is delayed for, in the hope of piggybacking it on data
+pub(crate) const DEFAULT_ACK_DELAY: Duration = Duration::from_millis(200);
+/// Upper bound of the acknowledgment delay. (RFC 9293 - Section 3.8.6.3)
+pub(crate) const MAX_ACK_DELAY: Duration = Duration::from_millis(500);
 
 bitflags! {
     pub(crate) struct Available: u8 {
         const READ = 1 << 0;
         const WRITE = 1 << 1;
+        const ESTAB = 1 << 2;
     }
 }
 
@@ -30,14 +79,11 @@ pub enum State {
     Closed,
 }
 
-// impl State {
-//     fn is_synchronized(&self) -> bool {
-//         match self {
-//             State::SynRcvd => false,
-//             _ => true,
-//         }
-//     }
-// }
+impl State {
+    fn is_synchronized(&self) -> bool {
+        !matches!(self, State::SynRcvd | State::SynSent)
+    }
+}
 
 #[derive(Debug)]
 pub struct Connection {
@@ -48,49 +94,357 @@ pub struct Connection {
     tcphdr: TcpHeader,
 
     pub(crate) inbuf: VecDeque<u8>,
+    /// Bytes of `inbuf` before the end of the urgent data, while some of it is unread
+    urgent_mark: Option<usize>,
+    /// Capacity of `inbuf`, the receive window never extends past the free space in it
+    recv_buffer_size: usize,
+    /// MTU of the interface, the largest packet we send
+    mtu: usize,
+    /// Largest segment we send, the peer's MSS capped by our MTU. (RFC 9293 - Section 3.7.1)
+    mss: usize,
+    /// Largest segment we receive, advertised to the peer in our SYN
+    recv_mss: usize,
+    /// Both sides sent the window scale option, so the window fields are scaled. It is offered in
+    /// our SYN until the peer answers. (RFC 7323 - Section 2)
+    window_scaling: bool,
+    /// Timestamps option, used for RTT measurement and PAWS
+    timestamps: Timestamps,
+    /// Both sides sent the SACK-permitted option, so out-of-order data is reported in SACK
+    /// blocks. It is offered in our SYN until the peer answers. (RFC 2018)
+    sack: bool,
+    /// Data the peer selectively acknowledged
+    scoreboard: Scoreboard,
+    /// Duplicate acknowledgments received in a row
+    dup_acks: usize,
+    /// Congestion control limiting the bytes in flight
+    congestion_control: Box<dyn CongestionControl>,
+    /// Constructor of `congestion_control`, to start it over once the MSS is known
+    new_congestion_control: CongestionControlFactory,
+    /// Delivery rate of the data we send, sampled for the congestion control
+    delivery_rate: DeliveryRate,
+    /// Earliest time the next new segment may be sent at, when the congestion control paces them
+    next_send: Instant,
+    /// Explicit Congestion Notification, letting routers mark congestion rather than drop
+    ecn: Ecn,
+    /// Segments received out of order, waiting for the gap before them to be filled
+    reassembly: Reassembly,
+    /// Data queued by the user, starting at the first unacknowledged byte. Bytes are only dropped
+    /// once `SND.UNA` moves past them.
     pub(crate) outbuf: VecDeque<u8>,
+    /// Sequence number of our FIN, once the sending side is closed
+    fin_seq: Option<u32>,
+    /// The user shut down the receiving side, further inbound data is discarded
+    read_shutdown: bool,
+    /// Bytes received since we last sent an acknowledgment
+    recv_unacked: usize,
+    /// Largest segment text received, i.e. a full-sized segment from the peer, which may be
+    /// smaller than `recv_mss` once its options are accounted for
+    recv_seg_max: usize,
+    /// Nagle's algorithm is disabled, small segments are sent even with data in flight
+    nodelay: bool,
+    timers: Timers,
+    /// Error the connection was torn down with, reported to its user
+    error: Option<io::ErrorKind>,
+    /// A user (i.e. a `TcpStream` or `Tcp::connect`) holds the connection, so it isn't deleted
+    /// once closed
+    attached: bool,
 }
 
 impl Connection {
     pub(crate) fn is_recv_closed(&self) -> bool {
-        if let State::TimeWait | State::CloseWait | State::Closing = self.state {
-            true
-        } else {
-            false
-        }
+        self.read_shutdown
+            || matches!(
+                self.state,
+                State::TimeWait
+                    | State::CloseWait
+                    | State::Closing
+                    | State::LastAck
+                    | State::Closed
+            )
     }
 
     fn availability(&self) -> Available {
+        if self.error.is_some() {
+            return Available::all();
+        }
+
         let mut availability = Available::empty();
         if self.is_recv_closed() || !self.inbuf.is_empty() {
             availability |= Available::READ;
         }
-        // TODO: set Available::WRITE
+        if self.outbuf.len() < TRANSMISSION_QLEN_SIZE {
+            availability |= Available::WRITE;
+        }
+        if self.is_established() {
+            availability |= Available::ESTAB;
+        }
         availability
     }
 
+    /// Checks whether the connection can be deleted
+    ///
+    /// Closed connections are kept while they have a user, to report their error or EOF to it.
     pub(crate) fn is_closed(&self) -> bool {
-        // TODO: Verify this is the only state where we delete the connection, otherwise, we only
-        // delete connections after timers that are also in TIME-WAIT states.
-        if let State::Closed = self.state {
+        matches!(self.state, State::Closed) && !self.attached
+    }
+
+    /// Checks whether the sending side of the connection is closed
+    pub(crate) fn is_send_closed(&self) -> bool {
+        self.fin_seq.is_some() || matches!(self.state, State::Closed)
+    }
+
+    /// Closes the sending side of the connection, our FIN is sent once all the data queued in
+    /// `outbuf` is sent. (RFC 9293 - Section 3.10.4)
+    pub(crate) fn close(&mut self) {
+        if self.fin_seq.is_some() {
+            return;
+        }
+
+        match self.state {
+            State::SynSent => {
+                // Nothing was promised to the peer yet
+                self.terminate(None);
+                return;
+            }
+            // Queued for processing after entering ESTABLISHED
+            State::SynRcvd => {}
+            State::Estab => self.state = State::FinWait1,
+            State::CloseWait => self.state = State::LastAck,
+            _ => return,
+        }
+        self.fin_seq = Some(self.data_start().wrapping_add(self.outbuf.len() as u32));
+    }
+
+    /// Shuts down the receiving side of the connection, discarding what was received and not yet
+    /// read. Inbound data is still acknowledged, but no longer delivered to the user.
+    pub(crate) fn shutdown_read(&mut self) {
+        self.read_shutdown = true;
+        self.inbuf.clear();
+        self.urgent_mark = None;
+        self.update_recv_window();
+    }
+
+    /// Checks whether data was received and not yet read by the user
+    pub(crate) fn has_unread(&self) -> bool {
+        !self.inbuf.is_empty()
+    }
+
+    /// Marks the connection as held by a user
+    pub(crate) fn attach(&mut self) {
+        self.attached = true;
+    }
+
+    /// Marks the connection as no longer held by a user, it is deleted once closed
+    pub(crate) fn detach(&mut self) {
+        self.attached = false;
+    }
+
+    pub(crate) fn error(&self) -> Option<io::ErrorKind> {
+        self.error
+    }
+
+    pub(crate) fn is_established(&self) -> bool {
+        !matches!(self.state, State::Closed | State::SynRcvd | State::SynSent)
+    }
+
+    pub(crate) fn retransmission_limit(&self) -> u32 {
+        self.timers.max_retries
+    }
+
+    pub(crate) fn set_retransmission_limit(&mut self, limit: u32) {
+        self.timers.max_retries = limit;
+    }
+
+    pub(crate) fn keepalive(&self) -> Option<Keepalive> {
+        self.timers.keepalive
+    }
+
+    pub(crate) fn set_keepalive(&mut self, keepalive: Option<Keepalive>) {
+        self.timers.keepalive = keepalive;
+        self.timers.keepalive_probes = 0;
+    }
+
+    pub(crate) fn nodelay(&self) -> bool {
+        self.nodelay
+    }
+
+    pub(crate) fn set_nodelay(&mut self, nodelay: bool) {
+        self.nodelay = nodelay;
+    }
+
+    /// Enters the CLOSED state, flushing all the segment queues and reporting `error` to the
+    /// user, if any
+    fn terminate(&mut self, error: Option<io::ErrorKind>) {
+        self.state = State::Closed;
+        self.error = error;
+        self.inbuf.clear();
+        self.urgent_mark = None;
+        self.reassembly.clear();
+        self.outbuf.clear();
+        self.timers.clear();
+        self.scoreboard.clear();
+        self.delivery_rate.clear();
+    }
+
+    /// Opens the receive window to the free space in `inbuf`, once it can grow by at least the
+    /// smaller of an MSS and half the buffer, to avoid the silly window syndrome.
+    /// (RFC 9293 - Section 3.8.6.2.2)
+    ///
+    /// Returns whether the window was opened
+    fn update_recv_window(&mut self) -> bool {
+        let max_wnd = (u16::MAX as usize) << self.recv.wnd_shift;
+        let free = cmp::min(
+            self.recv_buffer_size.saturating_sub(self.inbuf.len()),
+            max_wnd,
+        );
+        let threshold = cmp::min(self.recv_buffer_size / 2, self.recv_mss);
+        if free >= self.recv.wnd as usize + threshold {
+            self.recv.wnd = free as u32;
             true
         } else {
             false
         }
     }
 
-    pub(crate) fn is_established(&self) -> bool {
-        if let State::Closed | State::SynRcvd | State::SynSent = self.state {
-            false
+    /// Reads from `inbuf` into `buf`, stopping at the urgent mark so that the user can tell where
+    /// the urgent data ends.
+    ///
+    /// Returns a result containing the number of bytes read
+    pub(crate) fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self
+            .urgent_mark
+            .map_or(buf.len(), |mark| cmp::min(buf.len(), mark));
+        let (head, tail) = self.inbuf.as_slices();
+        let mut nread = cmp::min(head.len(), len);
+        buf[..nread].copy_from_slice(&head[..nread]);
+        let tread = cmp::min(len - nread, tail.len());
+        buf[nread..nread + tread].copy_from_slice(&tail[..tread]);
+        nread += tread;
+        drop(self.inbuf.drain(..nread));
+        self.urgent_mark = self
+            .urgent_mark
+            .map(|mark| mark - nread)
+            .filter(|&mark| mark > 0);
+
+        // Send a window update if the read made enough room
+        self.on_read()?;
+        Ok(nread)
+    }
+
+    /// Bytes `read` returns before the end of the urgent data, if some of it is unread
+    pub(crate) fn urgent_mark(&self) -> Option<usize> {
+        self.urgent_mark
+    }
+
+    /// Queues urgent data, moving the urgent pointer past everything queued so far
+    pub(crate) fn mark_urgent(&mut self) {
+        self.send.up = Some(self.data_start().wrapping_add(self.outbuf.len() as u32));
+    }
+
+    /// Tells the peer about the space a read freed up in `inbuf`, if the receive window opened
+    /// enough for it.
+    fn on_read(&mut self) -> io::Result<()> {
+        if self.update_recv_window()
+            && matches!(self.state, State::Estab | State::FinWait1 | State::FinWait2)
+        {
+            self.write(self.send.nxt, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the options of the peer's SYN. The ones it didn't send are disabled, as an option
+    /// is only used if both sides sent it.
+    fn negotiate_options(&mut self, tcphdr: &TcpHeaderSlice) {
+        match peer_window_shift(tcphdr) {
+            Some(shift) if self.window_scaling => self.send.wnd_shift = shift,
+            _ => {
+                self.window_scaling = false;
+                self.send.wnd_shift = 0;
+                self.recv.wnd_shift = 0;
+            }
+        }
+
+        if !peer_sack_permitted(tcphdr) {
+            self.sack = false;
+        }
+
+        if !Ecn::is_setup(tcphdr) {
+            self.ecn.enabled = false;
+        }
+
+        match peer_timestamp(tcphdr) {
+            Some((tsval, _)) if self.timestamps.enabled => {
+                self.timestamps.set_recent(tsval, Instant::now())
+            }
+            _ => self.timestamps.enabled = false,
+        }
+
+        // Every segment carries the timestamps, leave room for them
+        let options_len = if self.timestamps.enabled {
+            TIMESTAMPS_LEN
         } else {
-            true
+            0
+        };
+        let mss = cmp::min(peer_mss(tcphdr), self.mtu - HEADERS_LEN);
+        self.mss = cmp::max(mss.saturating_sub(options_len), 1);
+        self.congestion_control = (self.new_congestion_control)(self.mss);
+    }
+
+    pub(crate) fn set_congestion_control(
+        &mut self,
+        new_congestion_control: CongestionControlFactory,
+    ) {
+        self.new_congestion_control = new_congestion_control;
+        self.congestion_control = new_congestion_control(self.mss);
+    }
+
+    /// Checks whether the congestion window was reduced less than a round trip ago, either by a
+    /// loss recovery or in response to an echoed congestion mark
+    fn in_recovery(&self) -> bool {
+        self.scoreboard.in_recovery() || self.ecn.in_cwr()
+    }
+
+    /// Estimates the bytes in flight, leaving out the ones the peer reported as received and, after
+    /// a timeout, the ones deemed lost that weren't retransmitted yet. (RFC 5681 - Section 3.1)
+    fn pipe(&self) -> usize {
+        if self.sack || self.scoreboard.timed_out() {
+            return self.scoreboard.pipe(self.send.una, self.send.nxt, self.mss);
+        }
+
+        // Every duplicate acknowledgment is a segment that left the network
+        let flight_size = self.send.nxt.wrapping_sub(self.send.una) as usize;
+        if self.scoreboard.in_recovery() {
+            flight_size.saturating_sub(self.dup_acks * self.mss)
+        } else {
+            flight_size
+        }
+    }
+
+    /// Measures the RTT from the timestamp echoed by an acknowledgment. (RFC 7323 - Section 4.1)
+    fn timestamp_rtt(&self, tcphdr: &TcpHeaderSlice) -> Option<Duration> {
+        match peer_timestamp(tcphdr) {
+            Some((_, tsecr)) if self.timestamps.enabled && tcphdr.ack() && tsecr != 0 => {
+                Some(self.timestamps.rtt(tsecr))
+            }
+            _ => None,
+        }
+    }
+
+    /// Sequence number of the first byte in `outbuf`
+    ///
+    /// Until our SYN is acknowledged it occupies `SND.UNA`, so the data starts right after it.
+    fn data_start(&self) -> u32 {
+        if self.state.is_synchronized() {
+            self.send.una
+        } else {
+            self.send.una.wrapping_add(1)
         }
     }
 }
 
 /// State of the Send Sequence Space. (RFC 9293 - Section 3.3.1 - Figure 3)
 ///
-/// ```
+/// ```text
 ///             1         2          3          4
 ///        ----------|----------|----------|----------
 ///               SND.UNA    SND.NXT    SND.UNA
@@ -108,9 +462,11 @@ struct SendSequenceSpace {
     /// next
     nxt: u32,
     /// window
-    wnd: u16,
-    /// urgent pointer
-    up: bool,
+    wnd: u32,
+    /// shift count the window fields we receive are scaled by
+    wnd_shift: u8,
+    /// urgent pointer, the sequence number following the urgent data, until it is acknowledged
+    up: Option<u32>,
     /// segment sequence number used for last window update
     wl1: u32,
     /// segment acknowledgment number used for last window update
@@ -124,7 +480,7 @@ struct SendSequenceSpace {
 
 /// State of the Receive Sequence Space. (RFC 9293 - Section 3.3.1 - Figure 4)
 ///
-/// ```
+/// ```text
 ///    1          2          3
 ///             ----------|----------|----------
 ///                    RCV.NXT    RCV.NXT
@@ -139,41 +495,382 @@ struct RecvSequenceSpace {
     /// next
     nxt: u32,
     /// window
-    wnd: u16,
-    /// urgent pointer
-    up: bool,
+    wnd: u32,
+    /// shift count the window fields we send are scaled by
+    wnd_shift: u8,
+    /// urgent pointer, the highest one received, if any
+    up: Option<u32>,
     /// initial receive sequence number
     irs: u32,
 }
 
+/// A segment that was sent but not yet fully acknowledged
+#[derive(Debug)]
+struct Segment {
+    /// length in sequence space, including the SYN and FIN
+    len: u32,
+    /// time of the last transmission
+    sent_at: Instant,
+    /// the segment was retransmitted, so its RTT is ambiguous (Karn's algorithm)
+    retransmitted: bool,
+}
+
+/// Retransmission queue (RFC 6298) and the other timers of a connection
+#[derive(Debug)]
+struct Timers {
+    /// segments in flight, keyed by their sequence number
+    queue: BTreeMap<u32, Segment>,
+    /// smoothed round-trip time
+    srtt: Option<Duration>,
+    /// round-trip time variation
+    rttvar: Duration,
+    /// retransmission timeout
+    rto: Duration,
+    /// expiry of the retransmission timer, if it is running
+    deadline: Option<Instant>,
+    /// consecutive retransmissions of the oldest unacknowledged segment
+    retries: u32,
+    /// retransmissions after which the connection is aborted
+    max_retries: u32,
+    /// expiry of the persist timer, if it is running
+    persist: Option<Instant>,
+    /// interval between two window probes
+    persist_timeout: Duration,
+    /// expiry of the delayed acknowledgment timer, if an acknowledgment is pending
+    delayed_ack: Option<Instant>,
+    /// time an acknowledgment is delayed for
+    ack_delay: Duration,
+    /// expiry of the TIME-WAIT state
+    time_wait: Option<Instant>,
+    /// expiry of the FIN-WAIT-2 state, once the user let go of the connection
+    fin_wait2: Option<Instant>,
+    /// maximum segment lifetime
+    msl: Duration,
+    /// keepalive settings, if the connection sends keepalives
+    keepalive: Option<Keepalive>,
+    /// time a segment was last received at
+    last_received: Instant,
+    /// keepalive probes sent since a segment was last received
+    keepalive_probes: u32,
+}
+
+/// Keepalive settings of a connection. (RFC 9293 - Section 3.8.4)
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Keepalive {
+    /// time without receiving anything before the first probe
+    pub(crate) idle: Duration,
+    /// time between two unanswered probes
+    pub(crate) interval: Duration,
+    /// unanswered probes after which the connection is aborted
+    pub(crate) retries: u32,
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Timers {
+            queue: BTreeMap::new(),
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: INITIAL_RTO,
+            deadline: None,
+            retries: 0,
+            max_retries: DEFAULT_RETRANSMISSION_LIMIT,
+            persist: None,
+            persist_timeout: INITIAL_RTO,
+            delayed_ack: None,
+            ack_delay: DEFAULT_ACK_DELAY,
+            time_wait: None,
+            fin_wait2: None,
+            msl: DEFAULT_MSL,
+            keepalive: None,
+            last_received: Instant::now(),
+            keepalive_probes: 0,
+        }
+    }
+}
+
+impl Timers {
+    /// Records a segment of `len` sequence numbers starting at `seq` that was just sent, and
+    /// starts the retransmission timer if it isn't running. (RFC 6298 - Section 5.1)
+    fn on_send(&mut self, seq: u32, len: u32, now: Instant) {
+        // Everything overlapping a retransmission is ambiguous (Karn's algorithm), and the
+        // retransmissions need not line up with the segments first sent
+        let end = seq.wrapping_add(len);
+        let mut retransmitted = false;
+        for (&start, segment) in self.queue.iter_mut() {
+            if is_after(end, start) && is_after(start.wrapping_add(segment.len), seq) {
+                segment.retransmitted = true;
+                retransmitted = true;
+            }
+        }
+
+        let segment = self.queue.entry(seq).or_insert(Segment {
+            len,
+            sent_at: now,
+            retransmitted,
+        });
+        segment.len = cmp::max(segment.len, len);
+        segment.sent_at = now;
+
+        self.deadline.get_or_insert(now + self.rto);
+    }
+
+    /// Drops the segments acknowledged by `ack` from the queue, samples the RTT from the ones
+    /// that were never retransmitted, and restarts or stops the retransmission timer.
+    /// (RFC 6298 - Section 5.2 and 5.3)
+    ///
+    /// A `timestamp_rtt` measured from the timestamps option is used when only retransmitted
+    /// segments are acknowledged, as it isn't ambiguous for them. The timestamps only count
+    /// milliseconds though, so it is otherwise less precise than the time the segments were sent
+    /// at.
+    ///
+    /// Returns the RTT sample taken, if any
+    fn on_ack(
+        &mut self,
+        ack: u32,
+        timestamp_rtt: Option<Duration>,
+        now: Instant,
+    ) -> Option<Duration> {
+        let mut acked = false;
+        let mut rtt = None;
+        self.queue.retain(|&seq, segment| {
+            if is_after(seq.wrapping_add(segment.len), ack) {
+                return true;
+            }
+            if !segment.retransmitted {
+                let sample = now - segment.sent_at;
+                rtt = Some(rtt.map_or(sample, |rtt: Duration| cmp::min(rtt, sample)));
+            }
+            acked = true;
+            false
+        });
+
+        let rtt = rtt.or(timestamp_rtt.filter(|_| acked));
+        if let Some(rtt) = rtt {
+            self.on_rtt_sample(rtt);
+        }
+        self.retries = 0;
+        self.deadline = if self.queue.is_empty() {
+            None
+        } else {
+            Some(now + self.rto)
+        };
+        rtt
+    }
+
+    /// Updates the smoothed round-trip time and the retransmission timeout with a new
+    /// measurement. (RFC 6298 - Section 2)
+    fn on_rtt_sample(&mut self, rtt: Duration) {
+        let srtt = match self.srtt {
+            None => {
+                self.rttvar = rtt / 2;
+                rtt
+            }
+            Some(srtt) => {
+                self.rttvar = (self.rttvar * 3 + srtt.abs_diff(rtt)) / 4;
+                (srtt * 7 + rtt) / 8
+            }
+        };
+        self.srtt = Some(srtt);
+        self.rto = (srtt + cmp::max(CLOCK_GRANULARITY, self.rttvar * 4)).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Drops the segments in flight and stops the retransmission, persist and delayed
+    /// acknowledgment timers
+    fn clear(&mut self) {
+        self.queue.clear();
+        self.deadline = None;
+        self.persist = None;
+        self.delayed_ack = None;
+    }
+
+    /// Starts, or restarts, the 2 MSL timeout of the TIME-WAIT state
+    fn restart_time_wait(&mut self, now: Instant) {
+        self.clear();
+        self.time_wait = Some(now + self.msl * 2);
+    }
+
+    /// Checks whether the retransmission timer expired, backing off the timeout if so.
+    /// (RFC 6298 - Section 5.5 and 5.6)
+    fn on_expiry(&mut self, now: Instant) -> bool {
+        match self.deadline {
+            Some(deadline) if deadline <= now => {
+                self.retries += 1;
+                self.rto = cmp::min(self.rto * 2, MAX_RTO);
+                self.deadline = Some(now + self.rto);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Records that a segment was received, which answers the keepalive probes
+    fn on_receive(&mut self, now: Instant) {
+        self.last_received = now;
+        self.keepalive_probes = 0;
+    }
+
+    /// Checks whether a keepalive probe is due, i.e. nothing was received for the idle time,
+    /// nor for an interval after each probe. (RFC 9293 - Section 3.8.4)
+    fn on_keepalive(&mut self, now: Instant) -> bool {
+        let Some(keepalive) = self.keepalive else {
+            return false;
+        };
+        // A probe too far in the future to be represented is never due
+        let due = keepalive
+            .interval
+            .checked_mul(self.keepalive_probes)
+            .and_then(|probes| keepalive.idle.checked_add(probes))
+            .and_then(|wait| self.last_received.checked_add(wait));
+        if due.is_none_or(|due| due > now) {
+            return false;
+        }
+        self.keepalive_probes += 1;
+        true
+    }
+
+    /// Checks whether the persist timer expired, starting it at the retransmission timeout if it
+    /// isn't running and backing it off after every probe. (RFC 9293 - Section 3.8.6.1)
+    fn on_persist(&mut self, now: Instant) -> bool {
+        match self.persist {
+            None => {
+                self.persist_timeout = self.rto;
+                self.persist = Some(now + self.persist_timeout);
+                false
+            }
+            Some(persist) if persist <= now => {
+                self.persist_timeout = cmp::min(self.persist_timeout * 2, MAX_RTO);
+                self.persist = Some(now + self.persist_timeout);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
 impl Connection {
-    /// Takes a nic and payload and writes an IP packet to the nic
+    /// Writes an IP packet carrying at most `limit` bytes of `outbuf`, starting at sequence
+    /// number `seq`, to the nic. The SYN and FIN flags are set if the segment covers their
+    /// sequence numbers, which lets the same routine send new segments and retransmissions.
+    ///
     /// Returns a result containing the number of payload bytes written to the nic
-    fn write(&mut self, payload: &[u8]) -> io::Result<usize> {
-        self.tcphdr.ack = true;
-        let mut buf = [0u8; 1500];
+    fn write(&mut self, seq: u32, limit: usize) -> io::Result<usize> {
+        let mut buf = vec![0u8; self.mtu];
 
-        // Set the ip header payload
-        let payload_len = {
-            let full_payload = self.iphdr.header_len() + self.tcphdr.header_len() + payload.len();
-            match buf.len().cmp(&full_payload) {
-                Ordering::Less | Ordering::Equal => buf.len() - self.iphdr.header_len(),
-                Ordering::Greater => self.tcphdr.header_len() + payload.len(),
+        self.tcphdr.syn = !self.state.is_synchronized() && seq == self.send.iss;
+        self.tcphdr.ack = !matches!(self.state, State::SynSent);
+
+        // The MSS, window scale and SACK-permitted options are only sent along with the SYN, while
+        // timestamps are sent on every segment once agreed on, and SACK blocks on the ones
+        // acknowledging data
+        let mut options = vec![];
+        if self.tcphdr.syn {
+            options.push(TcpOptionElement::MaximumSegmentSize(self.recv_mss as u16));
+            if self.window_scaling {
+                options.push(TcpOptionElement::Noop);
+                options.push(TcpOptionElement::WindowScale(self.recv.wnd_shift));
+            }
+            if self.sack {
+                options.push(TcpOptionElement::Noop);
+                options.push(TcpOptionElement::Noop);
+                options.push(TcpOptionElement::SelectiveAcknowledgementPermitted);
+            }
+        }
+        if self.timestamps.enabled {
+            options.push(TcpOptionElement::Noop);
+            options.push(TcpOptionElement::Noop);
+            options.push(TcpOptionElement::Timestamp(
+                self.timestamps.now(),
+                self.timestamps.recent(),
+            ));
+        }
+        if self.sack && self.tcphdr.ack && !self.tcphdr.syn {
+            // Each block takes 8 bytes, after 2 bytes of padding and 2 for the option kind and
+            // length, so only 3 fit along with the timestamps, and fewer when a small MTU leaves
+            // less room than the TCP header does. A byte of data must still fit, for the
+            // segments sent to make progress.
+            self.tcphdr
+                .set_options(&options)
+                .expect("TCP options are too long!");
+            let room = cmp::min(TcpHeader::MAX_LEN, self.mtu - self.iphdr.header_len() - 1)
+                .saturating_sub(self.tcphdr.header_len());
+            let max = cmp::min(room.saturating_sub(4) / 8, 4);
+            let blocks = self.reassembly.sack_blocks(max);
+            if let Some((&first, rest)) = blocks.split_first() {
+                let mut others = [None; 3];
+                for (other, &block) in others.iter_mut().zip(rest) {
+                    *other = Some(block);
+                }
+                options.push(TcpOptionElement::Noop);
+                options.push(TcpOptionElement::Noop);
+                options.push(TcpOptionElement::SelectiveAcknowledgement(first, others));
             }
+        }
+        self.tcphdr
+            .set_options(&options)
+            .expect("TCP options are too long!");
+
+        // Slice the payload out of the outbuf, a sequence number before the data start (i.e. the
+        // SYN) gives an empty payload
+        let data_start = self.data_start();
+        let max_payload = buf.len() - self.iphdr.header_len() - self.tcphdr.header_len();
+        let payload = {
+            let start = cmp::min(seq.wrapping_sub(data_start) as usize, self.outbuf.len());
+            let end = cmp::min(start + cmp::min(limit, max_payload), self.outbuf.len());
+            self.tcphdr.psh = end > start && end == self.outbuf.len();
+            &self.outbuf.make_contiguous()[start..end]
         };
+        let seg_end = seq.wrapping_add(payload.len() as u32);
+        self.tcphdr.fin = self.fin_seq == Some(seg_end);
+
+        // The urgent pointer is sent as an offset from the sequence number, on every segment before
+        // it that it fits in. (RFC 9293 - Section 3.8.5)
+        match self.send.up {
+            Some(up) if is_after(up, seq) && up.wrapping_sub(seq) <= u16::MAX as u32 => {
+                self.tcphdr.urg = true;
+                self.tcphdr.urgent_pointer = up.wrapping_sub(seq) as u16;
+            }
+            _ => {
+                self.tcphdr.urg = false;
+                self.tcphdr.urgent_pointer = 0;
+            }
+        }
+
+        // ECN is requested by our SYN and agreed to by our SYN-ACK, afterwards ECE echoes the
+        // congestion marks received and CWR answers the ones echoed by the peer. Only new data is
+        // ECN-capable, not pure acknowledgments nor retransmissions. (RFC 3168 - Section 6.1)
+        let new_data = !payload.is_empty() && !is_after(self.send.nxt, seq);
+        if self.tcphdr.syn {
+            self.tcphdr.ece = self.ecn.enabled;
+            self.tcphdr.cwr = self.ecn.enabled && !self.tcphdr.ack;
+        } else {
+            self.tcphdr.ece = self.ecn.echo();
+            self.tcphdr.cwr = new_data && self.ecn.take_cwr();
+        }
+        self.iphdr.ecn = if self.ecn.enabled && new_data {
+            ECT0
+        } else {
+            Ipv4Ecn::ZERO
+        };
+
+        // Set the ip header payload
         self.iphdr
-            .set_payload_len(payload_len)
+            .set_payload_len(self.tcphdr.header_len() + payload.len())
             .expect("Payload length is too big!");
 
-        // Set the tcp header seqn, ackn, and checksum
-        self.tcphdr.sequence_number = self.send.nxt;
+        // Set the tcp header seqn, ackn, window, and checksum
+        self.tcphdr.sequence_number = seq;
         self.tcphdr.acknowledgment_number = self.recv.nxt;
+        // The window of a SYN is never scaled
+        let wnd_shift = if self.tcphdr.syn {
+            0
+        } else {
+            self.recv.wnd_shift
+        };
+        self.tcphdr.window_size = cmp::min(self.recv.wnd >> wnd_shift, u16::MAX as u32) as u16;
         self.tcphdr.checksum = self
             .tcphdr
-            .calc_checksum_ipv4(
-                &self.iphdr,
-                &payload[..(payload_len - self.tcphdr.header_len())],
-            )
+            .calc_checksum_ipv4(&self.iphdr, payload)
             .expect("Payload is too big!");
 
         // Write to buffer and then to the nic
@@ -184,28 +881,117 @@ impl Connection {
             let payload_bytes = unwritten.write(payload)?;
             (unwritten.len(), payload_bytes)
         };
-        NIC::get_mut_ref()?
+        Nic::get_mut_ref()?
             .lock()
             .unwrap()
             .send(&buf[..buf.len() - unwritten])?;
 
-        // Update the send next sequence number
-        self.send.nxt = self
-            .send
-            .nxt
-            .wrapping_add(payload_bytes as u32)
-            .wrapping_add(if self.tcphdr.syn || self.tcphdr.fin {
-                1
-            } else {
-                0
-            });
+        // Update the send next sequence number, retransmissions don't move it backwards
+        let nxt = seg_end.wrapping_add(if self.tcphdr.syn || self.tcphdr.fin {
+            1
+        } else {
+            0
+        });
+        if is_after(nxt, self.send.nxt) {
+            self.send.nxt = nxt;
+        }
+
+        // Keep track of the segment until it is acknowledged
+        let seg_len = nxt.wrapping_sub(seq);
+        if seg_len > 0 {
+            let now = Instant::now();
+            self.timers.on_send(seq, seg_len, now);
+            self.delivery_rate.on_send(seq, seg_len, now);
+        }
+
+        // The segment acknowledges everything received so far
+        if self.tcphdr.ack {
+            self.recv_unacked = 0;
+            self.timers.delayed_ack = None;
+            self.timestamps.on_send(self.recv.nxt);
+        }
 
         Ok(payload_bytes)
     }
 
-    // TODO: This function should send the packets itself rather than using Connection::write,
-    //       because it needs careful handling of the sequence and acknowledgement numbers, it
-    //       should also not modify the connection send and receive sequence spaces.
+    /// Sends as much of the unsent `outbuf` data as the send and congestion windows allow, in
+    /// segments of at most `mss` bytes, followed by our FIN once all the data is sent.
+    ///
+    /// Unless `nodelay` is set, a segment smaller than `mss` is held back while data is in flight,
+    /// so that small writes get coalesced. (RFC 9293 - Section 3.7.4) The last segment before our
+    /// FIN and urgent data are never held back.
+    ///
+    /// When the congestion control sets a pacing rate, segments are spaced out to match it. The
+    /// schedule may fall behind by up to a clock tick, which is then sent in a burst.
+    ///
+    /// Returns whether any segment was sent
+    pub(crate) fn transmit(&mut self) -> io::Result<bool> {
+        if !self.state.is_synchronized() {
+            return Ok(false);
+        }
+
+        let mut sent = false;
+        loop {
+            let sent_len = self.send.nxt.wrapping_sub(self.data_start()) as usize;
+            if sent_len > self.outbuf.len() {
+                // The FIN is already sent
+                break;
+            }
+
+            let unsent = self.outbuf.len() - sent_len;
+            if unsent == 0 {
+                // The application, rather than the congestion window, limits the delivery rate
+                let pipe = self.pipe();
+                if pipe < self.congestion_control.cwnd() {
+                    self.delivery_rate.on_app_limited(pipe);
+                }
+                if self.fin_seq == Some(self.send.nxt) {
+                    self.write(self.send.nxt, 0)?;
+                    sent = true;
+                }
+                break;
+            }
+
+            let wnd_end = self.send.una.wrapping_add(self.send.wnd);
+            if !is_after(wnd_end, self.send.nxt) {
+                break;
+            }
+            let usable = wnd_end.wrapping_sub(self.send.nxt) as usize;
+
+            // The congestion window limits the bytes in flight
+            let cwnd_usable = self.congestion_control.cwnd().saturating_sub(self.pipe());
+            if cwnd_usable == 0 {
+                break;
+            }
+
+            let len = cmp::min(cmp::min(unsent, usable), cmp::min(cwnd_usable, self.mss));
+
+            // Nagle's algorithm
+            let in_flight = self.send.nxt != self.send.una;
+            let closing = len == unsent && self.fin_seq.is_some();
+            let urgent = self.send.up.is_some_and(|up| is_after(up, self.send.nxt));
+            if len < self.mss && in_flight && !self.nodelay && !closing && !urgent {
+                break;
+            }
+
+            if let Some(rate) = self.congestion_control.pacing_rate() {
+                let now = Instant::now();
+                if self.next_send > now {
+                    break;
+                }
+                let interval = Duration::from_nanos(
+                    (len as u128 * 1_000_000_000 / cmp::max(rate, 1) as u128) as u64,
+                );
+                let behind = now.checked_sub(CLOCK_GRANULARITY).unwrap_or(now);
+                self.next_send = cmp::max(self.next_send, behind) + interval;
+            }
+
+            self.write(self.send.nxt, len)?;
+            sent = true;
+        }
+        Ok(sent)
+    }
+
     /// Sends TCP RST packets
     ///
     /// In accordance to RFC 9293 - Section 3.5.1, an RST packet is sent in when a TCP packet
@@ -240,29 +1026,37 @@ impl Connection {
     /// containing the current send sequence number and an acknowledgment indicating the next
     /// sequence number expected to be received, and the connection remains in the same state.
     ///
-    // fn send_rst<'a>(
-    //     &mut self,
-    //     nic: &mut Iface,
-    //     tcphdr: &'a TcpHeaderSlice,
-    //     payload: &[u8],
-    // ) -> io::Result<()> {
-    //     if !self.state.is_synchronized() {
-    //         self.tcphdr.sequence_number = if tcphdr.ack() {
-    //             tcphdr.acknowledgment_number()
-    //         } else {
-    //             0
-    //         }
-    //         .wrapping_add(payload.len() as u32);
-
-    //         self.tcphdr.acknowledgment_number =
-    //             tcphdr.sequence_number().wrapping_add(payload.len() as u32);
-    //     }
-    //     self.tcphdr.rst = true;
-    //     self.tcphdr.ack = true;
-
-    //     self.write(nic, &[])?;
-    //     Ok(())
-    // }
+    /// The reset is built independently of `Connection::write`, so neither the sequence spaces
+    /// nor the tcp header of the connection are modified.
+    fn send_rst(&mut self, tcphdr: &TcpHeaderSlice, payload: &[u8]) -> io::Result<()> {
+        if !matches!(self.state, State::Closed | State::SynSent | State::SynRcvd) {
+            // Group 3, a challenge ACK: <SEQ=SND.NXT><ACK=RCV.NXT><CTL=ACK>
+            self.write(self.send.nxt, 0)?;
+            return Ok(());
+        }
+
+        reply_rst(
+            self.iphdr.source,
+            self.iphdr.destination,
+            tcphdr,
+            payload.len(),
+        )
+    }
+
+    /// Aborts the connection, sending <SEQ=SND.NXT><CTL=RST> if the peer knows about it, and
+    /// enters the CLOSED state.
+    pub(crate) fn abort(&mut self) -> io::Result<()> {
+        if !matches!(self.state, State::SynSent | State::Closed) {
+            write_rst(
+                (self.iphdr.source, self.tcphdr.source_port),
+                (self.iphdr.destination, self.tcphdr.destination_port),
+                self.send.nxt,
+                None,
+            )?;
+        }
+        self.terminate(None);
+        Ok(())
+    }
 
     /// Resets all tcp header flags
     ///
@@ -282,138 +1076,237 @@ impl Connection {
     pub fn accept<'a>(
         iphdr: &'a Ipv4HeaderSlice,
         tcphdr: &'a TcpHeaderSlice,
+        iss: u32,
+        config: &TcpConfig,
     ) -> io::Result<Option<Self>> {
+        // A listening port resets anything acknowledging a connection it doesn't know of
+        if tcphdr.rst() {
+            return Ok(None);
+        }
+        if tcphdr.ack() {
+            send_rst(iphdr, tcphdr, 0)?;
+            return Ok(None);
+        }
         if !tcphdr.syn() {
-            // TODO: Send RST (RFC 9293 - Section 3.5.1 - Group 1)
             return Ok(None);
         }
 
-        // Create tcp and ip headers to send a syn_ack packet
-        let iss = 0;
-        let window_size = tcphdr.window_size();
+        // Create tcp and ip headers to send a syn_ack packet, the window grows past what fits in
+        // the unscaled window field of the SYN once established
+        let wnd = cmp::min(config.recv_buffer_size, u16::MAX as usize) as u16;
         let mut connection = Connection {
             state: State::SynRcvd,
             send: SendSequenceSpace {
                 iss,
                 una: iss,
                 nxt: iss,
-                wnd: window_size,
-                up: false,
-                wl1: 0,
+                wnd: tcphdr.window_size() as u32,
+                wnd_shift: 0,
+                up: None,
+                wl1: tcphdr.sequence_number(),
                 wl2: 0,
             },
             recv: RecvSequenceSpace {
-                nxt: tcphdr.sequence_number() + 1,
-                wnd: tcphdr.window_size(),
-                up: false,
+                nxt: tcphdr.sequence_number().wrapping_add(1),
+                wnd: wnd as u32,
+                wnd_shift: window_shift(config.recv_buffer_size),
+                up: None,
                 irs: tcphdr.sequence_number(),
             },
             iphdr: Ipv4Header::new(0, 64, IpNumber::TCP, iphdr.destination(), iphdr.source())
                 .expect("Payload is too big!"),
-            tcphdr: TcpHeader::new(
-                tcphdr.destination_port(),
-                tcphdr.source_port(),
-                iss,
-                window_size,
-            ),
+            tcphdr: TcpHeader::new(tcphdr.destination_port(), tcphdr.source_port(), iss, wnd),
             inbuf: VecDeque::default(),
+            urgent_mark: None,
+            recv_buffer_size: config.recv_buffer_size,
+            mtu: config.mtu,
+            mss: cmp::min(DEFAULT_MSS, config.mtu - HEADERS_LEN),
+            recv_mss: config.mtu - HEADERS_LEN,
+            window_scaling: true,
+            timestamps: Timestamps::new(iss),
+            sack: true,
+            scoreboard: Scoreboard::default(),
+            dup_acks: 0,
+            congestion_control: (config.congestion_control)(DEFAULT_MSS),
+            new_congestion_control: config.congestion_control,
+            delivery_rate: DeliveryRate::new(Instant::now()),
+            next_send: Instant::now(),
+            ecn: Ecn::new(config.ecn),
+            reassembly: Reassembly::default(),
             outbuf: VecDeque::default(),
+            fin_seq: None,
+            read_shutdown: false,
+            recv_unacked: 0,
+            recv_seg_max: 0,
+            nodelay: false,
+            timers: Timers {
+                max_retries: config.retransmission_limit,
+                ack_delay: config.ack_delay,
+                msl: config.msl,
+                ..Default::default()
+            },
+            error: None,
+            attached: false,
         };
-        connection.tcphdr.syn = true;
-        connection.tcphdr.ack = true;
 
-        connection.write(&[])?;
+        // Send the SYN-ACK, with the options the peer offered
+        connection.negotiate_options(tcphdr);
+        connection.write(iss, 0)?;
 
         Ok(Some(connection))
     }
 
-    pub(crate) fn on_packet<'a>(
+    pub(crate) fn on_packet(
         &mut self,
-        tcphdr: &'a TcpHeaderSlice,
+        tcphdr: &TcpHeaderSlice,
+        ecn: Ipv4Ecn,
         payload: &[u8],
     ) -> io::Result<Available> {
         // Validate segment. (RFC 9293 - Section 4.3)
         let seg_seq = tcphdr.sequence_number();
         let seg_ack = tcphdr.acknowledgment_number();
-        let seg_wnd = tcphdr.window_size();
+        let seg_wnd = (tcphdr.window_size() as u32) << self.send.wnd_shift;
         let seg_len = payload.len() as u32 + if tcphdr.syn() || tcphdr.fin() { 1 } else { 0 };
+        if let State::Closed = self.state {
+            // The connection is only kept around to report its error to the user
+            self.send_rst(tcphdr, payload)?;
+            return Ok(self.availability());
+        }
         if let State::SynSent = self.state {
-            if !is_in_range_wrap(self.send.iss, seg_ack, self.send.nxt.wrapping_add(1))
-                && !is_in_range_wrap(
-                    self.send.una.wrapping_add(1),
-                    seg_ack,
-                    self.send.nxt.wrapping_add(1),
-                )
+            if tcphdr.ack()
+                && !is_in_range_wrap(self.send.iss, seg_ack, self.send.nxt.wrapping_add(1))
             {
-                if tcphdr.rst() {
-                    return Ok(self.availability());
+                // <SEQ=SEG.ACK><CTL=RST> (RFC 9293 - Section 3.5.1 - Group 2)
+                self.send_rst(tcphdr, payload)?;
+                return Ok(self.availability());
+            }
+
+            if tcphdr.rst() {
+                // Only a RST acknowledging our SYN is acceptable
+                if tcphdr.ack() {
+                    self.terminate(Some(io::ErrorKind::ConnectionRefused));
                 }
-                // TODO: send a reset
                 return Ok(self.availability());
             }
 
             if !tcphdr.syn() {
                 return Ok(self.availability());
             }
+
+            if !tcphdr.ack() {
+                // TODO: simultaneous open, enter SYN-RECEIVED and send a SYN-ACK
+                return Ok(self.availability());
+            }
+
             self.recv.nxt = seg_seq.wrapping_add(1);
             self.recv.irs = seg_seq;
+            self.negotiate_options(tcphdr);
             self.send.una = seg_ack;
+            let rtt = self.timestamp_rtt(tcphdr);
+            self.timers.on_ack(seg_ack, rtt, Instant::now());
+            // The window of a SYN is never scaled
+            self.send.wnd = tcphdr.window_size() as u32;
+            self.send.wl1 = seg_seq;
+            self.send.wl2 = seg_ack;
             self.state = State::Estab;
             self.reset_tcphdr_flags();
-            self.write(payload)?;
+            self.update_recv_window();
+
+            // Acknowledge the SYN-ACK, along with any data queued in the meantime
+            if !self.transmit()? {
+                self.write(self.send.nxt, 0)?;
+            }
             return Ok(self.availability());
         }
-        match (seg_len, self.recv.wnd) {
-            (0, 0) => {
-                if seg_seq != self.recv.nxt {
-                    return Ok(self.availability());
-                }
-            }
-            (0, _) => {
-                if !is_in_range_wrap(
-                    self.recv.nxt.wrapping_sub(1),
-                    seg_seq,
-                    self.recv.nxt.wrapping_add(self.recv.wnd as u32),
-                ) {
-                    // TODO: Send ACK
+        // Reject old duplicates, whose timestamp is older than the last one received. A segment
+        // without timestamps once they are agreed on is dropped. (RFC 7323 - Section 5.3)
+        let timestamp = peer_timestamp(tcphdr);
+        if self.timestamps.enabled && !tcphdr.rst() {
+            match timestamp {
+                None => return Ok(self.availability()),
+                Some((tsval, _)) if self.timestamps.is_old(tsval, Instant::now()) => {
+                    // <SEQ=SND.NXT><ACK=RCV.NXT><CTL=ACK>
+                    self.reset_tcphdr_flags();
+                    self.write(self.send.nxt, 0)?;
                     return Ok(self.availability());
                 }
+                Some(_) => {}
             }
-            (_, 0) => {
-                // TODO: IF the RCV.WND is zero, no segments will be acceptable, but special
-                // allowance should be made to accept valid ACKs, URGs, and RSTs.
+        }
 
-                // TODO: Send ACK
-                return Ok(self.availability());
-            }
+        let zero_window = self.recv.wnd == 0;
+        let acceptable = match (seg_len, self.recv.wnd) {
+            (0, 0) => seg_seq == self.recv.nxt,
+            (0, _) => is_in_range_wrap(
+                self.recv.nxt.wrapping_sub(1),
+                seg_seq,
+                self.recv.nxt.wrapping_add(self.recv.wnd),
+            ),
+            // Nothing fits in a zero window, but valid ACKs, URGs and RSTs are still processed,
+            // the text and FIN are dropped further down. (RFC 9293 - Section 3.10.7.4)
+            (_, 0) => seg_seq == self.recv.nxt,
             (_, _) => {
-                if !(is_in_range_wrap(
+                is_in_range_wrap(
                     self.recv.nxt.wrapping_sub(1),
                     seg_seq,
-                    self.recv.nxt.wrapping_add(self.recv.wnd as u32),
-                ) && is_in_range_wrap(
+                    self.recv.nxt.wrapping_add(self.recv.wnd),
+                ) || is_in_range_wrap(
                     self.recv.nxt.wrapping_sub(1),
                     seg_seq.wrapping_add(seg_len - 1),
-                    self.recv.nxt.wrapping_add(self.recv.wnd as u32),
-                )) {
-                    // TODO: Send ACK
-                    return Ok(self.availability());
+                    self.recv.nxt.wrapping_add(self.recv.wnd),
+                )
+            }
+        };
+        if !acceptable {
+            // If an incoming segment is not acceptable, an acknowledgment should be sent in reply
+            // (unless the RST bit is set, if so drop the segment and return):
+            // <SEQ=SND.NXT><ACK=RCV.NXT><CTL=ACK>
+            if !tcphdr.rst() {
+                self.reset_tcphdr_flags();
+                self.write(self.send.nxt, 0)?;
+
+                // The only thing that can arrive in TIME-WAIT is a retransmission of the remote
+                // FIN, acknowledge it and restart the 2 MSL timeout
+                if let (State::TimeWait, true) = (&self.state, tcphdr.fin()) {
+                    self.timers.restart_time_wait(Instant::now());
                 }
             }
+            return Ok(self.availability());
         }
-        // TODO: If an incoming segment is not acceptable, an acknowledgment should be sent in
-        // reply (unless the RST bit is set, if so drop the segment and return):
-        // <SEQ=SND.NXT><ACK=RCV.NXT><CTL=ACK>
 
-        // TODO: Segments with higher beginning sequence numbers (than RCV.NXT) SHOULD be held
-        // for later processing (SHLD-31).
+        if let (true, Some((tsval, _))) = (self.timestamps.enabled, timestamp) {
+            self.timestamps.on_receive(seg_seq, tsval, Instant::now());
+        }
+        let marked = self.ecn.on_receive(ecn, tcphdr.cwr());
+        self.timers.on_receive(Instant::now());
 
-        if tcphdr.syn() {
-            // TODO: Send a reset, any outstanding RECEIVEs and SEND should receive "reset"
-            // responses, all segment queues should be flushed, the user should also receive an
-            // unsolicited general "connection reset" signal, enter the CLOSED state, delete
-            // the TCB.
+        if tcphdr.rst() {
+            if seg_seq != self.recv.nxt {
+                // The RST might be a blind attack, send a challenge ACK instead of resetting the
+                // connection. (RFC 5961 - Section 3.2)
+                self.reset_tcphdr_flags();
+                self.write(self.send.nxt, 0)?;
+                return Ok(self.availability());
+            }
+
+            match self.state {
+                // The connection came from a passive open, the listener is not told about it
+                // unless it already handed it out
+                State::SynRcvd if !self.attached => self.terminate(None),
+                State::SynRcvd => self.terminate(Some(io::ErrorKind::ConnectionReset)),
+                State::Estab | State::FinWait1 | State::FinWait2 | State::CloseWait => {
+                    self.terminate(Some(io::ErrorKind::ConnectionReset))
+                }
+                _ => self.terminate(None),
+            }
+            return Ok(self.availability());
+        }
 
+        if tcphdr.syn() {
+            // A SYN in a synchronized state might be a blind attack, so it gets a challenge ACK
+            // rather than resetting the connection. (RFC 5961 - Section 4)
+            self.reset_tcphdr_flags();
+            self.send_rst(tcphdr, payload)?;
             return Ok(self.availability());
         }
 
@@ -422,158 +1315,646 @@ impl Connection {
             return Ok(self.availability());
         }
 
+        // Reset the tcp header flags regardless of the handler
+        self.reset_tcphdr_flags();
+
         if let State::SynRcvd = self.state {
-            if is_in_range_wrap(
-                self.send.una.wrapping_sub(1),
-                seg_ack,
-                self.send.nxt.wrapping_add(1),
-            ) {
-                self.state = State::Estab;
-            } else {
-                // TODO:
-                // <SEQ=SEG.ACK><CTL=RST>
-                // Send it
+            if !is_in_range_wrap(self.send.una, seg_ack, self.send.nxt.wrapping_add(1)) {
+                // <SEQ=SEG.ACK><CTL=RST> (RFC 9293 - Section 3.5.1 - Group 2)
+                self.send_rst(tcphdr, payload)?;
                 return Ok(self.availability());
             }
+            self.send.wnd = seg_wnd;
+            self.send.wl1 = seg_seq;
+            self.send.wl2 = seg_ack;
         }
 
-        if let State::Estab
-        | State::FinWait1
-        | State::FinWait2
-        | State::CloseWait
-        | State::Closing = self.state
+        let now = Instant::now();
+        let flight_size = self.send.nxt.wrapping_sub(self.send.una) as usize;
+        let mut acked = 0;
+        let mut rtt = None;
+        if is_in_range_wrap(self.send.una, seg_ack, self.send.nxt.wrapping_add(1)) {
+            // Drop the acknowledged bytes from the outbuf, an acknowledged FIN isn't part of it
+            let acked_data = cmp::min(
+                seg_ack.wrapping_sub(self.data_start()) as usize,
+                self.outbuf.len(),
+            );
+            drop(self.outbuf.drain(..acked_data));
+            acked = seg_ack.wrapping_sub(self.send.una) as usize;
+            self.send.una = seg_ack;
+            if self.send.up.is_some_and(|up| !is_after(up, seg_ack)) {
+                self.send.up = None;
+            }
+            rtt = self.timers.on_ack(seg_ack, self.timestamp_rtt(tcphdr), now);
+            self.dup_acks = 0;
+        } else if is_after(seg_ack, self.send.nxt) {
+            // Acknowledges something not yet sent
+            self.write(self.send.nxt, 0)?;
+            return Ok(self.availability());
+        } else if seg_ack == self.send.una
+            && payload.is_empty()
+            && !tcphdr.fin()
+            && seg_wnd == self.send.wnd
+            && flight_size > 0
         {
-            if is_in_range_wrap(self.send.una, seg_ack, self.send.nxt.wrapping_add(1)) {
-                self.send.una = seg_ack;
-
-                if (self.send.wl1 < seg_seq)
-                    || (self.send.wl1 == seg_seq && self.send.wl2 <= seg_ack)
-                {
-                    self.send.wnd = seg_wnd;
-                    self.send.wl1 = seg_seq;
-                    self.send.wl2 = seg_ack;
+            // A duplicate acknowledgment (RFC 5681 - Section 2)
+            self.dup_acks += 1;
+        }
+
+        let in_recovery = self.in_recovery();
+        let sack_blocks = if self.sack {
+            peer_sack_blocks(tcphdr)
+        } else {
+            vec![]
+        };
+        self.scoreboard
+            .on_ack(self.send.una, self.send.nxt, &sack_blocks);
+        self.ecn.on_ack(self.send.una);
+        let rate_sample = self.delivery_rate.on_ack(
+            self.send.una,
+            |start, end| self.scoreboard.is_sacked(start, end),
+            now,
+        );
+
+        // Let the congestion control know about the acknowledgment, the window doesn't grow
+        // during a loss recovery
+        let ack = Ack {
+            now,
+            acked,
+            flight_size,
+            mss: self.mss,
+            rtt,
+            una: self.send.una,
+            nxt: self.send.nxt,
+            delivered: self.delivery_rate.delivered(),
+            rate_sample,
+        };
+        if self.state.is_synchronized() {
+            if in_recovery && !self.in_recovery() {
+                self.congestion_control.on_recovery_end(&ack);
+            } else if in_recovery {
+                self.congestion_control.on_recovery_ack(&ack);
+            } else if acked > 0 {
+                self.congestion_control.on_ack(&ack);
+            }
+
+            // An echoed congestion mark gets the same response as a loss, without the
+            // retransmission, unless the window was already reduced during the last round trip.
+            // (RFC 3168 - Section 6.1.2)
+            if self.ecn.enabled && tcphdr.ece() && !self.ecn.in_cwr() {
+                if !self.scoreboard.in_recovery() {
+                    self.congestion_control.on_loss(&ack);
+                }
+                self.ecn.enter_cwr(self.send.nxt);
+            }
+        }
+
+        if is_after(seg_seq, self.send.wl1)
+            || (self.send.wl1 == seg_seq && !is_after(self.send.wl2, seg_ack))
+        {
+            self.send.wnd = seg_wnd;
+            self.send.wl1 = seg_seq;
+            self.send.wl2 = seg_ack;
+        }
+
+        let fin_acked = self
+            .fin_seq
+            .is_some_and(|fin_seq| is_after(self.send.una, fin_seq));
+        match self.state {
+            // The user may have closed the connection before it was established
+            State::SynRcvd if self.fin_seq.is_some() => self.state = State::FinWait1,
+            State::SynRcvd => {
+                self.state = State::Estab;
+                self.update_recv_window();
+            }
+            State::FinWait1 if fin_acked => self.state = State::FinWait2,
+            State::Closing if fin_acked => self.state = State::TimeWait,
+            State::LastAck if fin_acked => {
+                self.state = State::Closed;
+                return Ok(self.availability());
+            }
+            _ => {}
+        }
+
+        // Process the segment text, trimmed to the receive window. Only in-order data can have its
+        // acknowledgment delayed, anything else is acknowledged right away.
+        let mut fin = false;
+        let mut delay_ack = false;
+        if let State::Estab | State::FinWait1 | State::FinWait2 = self.state {
+            // Keep the highest urgent pointer received (RFC 9293 - Section 3.8.5)
+            let mut urgent = false;
+            if tcphdr.urg() && tcphdr.urgent_pointer() > 0 {
+                let seg_up = seg_seq.wrapping_add(tcphdr.urgent_pointer() as u32);
+                if self.recv.up.is_none_or(|up| is_after(seg_up, up)) {
+                    self.recv.up = Some(seg_up);
+                    urgent = true;
+                }
+            }
+
+            let nxt = self.recv.nxt;
+            let wnd_end = self.recv.nxt.wrapping_add(self.recv.wnd);
+            let text = &payload[..cmp::min(payload.len(), wnd_end.wrapping_sub(seg_seq) as usize)];
+            let text_end = seg_seq.wrapping_add(text.len() as u32);
+
+            if is_after(seg_seq, self.recv.nxt) {
+                // Segments with higher beginning sequence numbers (than RCV.NXT) SHOULD be held
+                // for later processing (SHLD-31).
+                self.reassembly.insert(self.recv.nxt, seg_seq, text);
+                if tcphdr.fin() && text.len() == payload.len() {
+                    self.reassembly.insert_fin(text_end);
                 }
             } else {
-                if let State::FinWait2 = self.state {
-                } else {
-                    if !is_duplicate(self.send.una, seg_ack, self.send.nxt) {
-                        // TODO: Send an ACK
-                        self.write(&[])?;
-                        return Ok(self.availability());
-                    }
-                    // if !self.state.is_synchronized() {
-                    //     // Send RST (RFC 9293 - Section 3.5.1 - Group 2)
-                    //     self.send_rst(nic, tcphdr, payload)?;
-                    // }
-
-                    // Not yet sent
+                // Skip the bytes that were already received
+                let skip = self.recv.nxt.wrapping_sub(seg_seq) as usize;
+                if skip < text.len() {
+                    self.inbuf.write_all(&text[skip..])?;
+                    self.recv.nxt = text_end;
+                }
+
+                // Deliver the held segments that became contiguous
+                let mut filled_gap = false;
+                while let Some(chunk) = self.reassembly.pop(self.recv.nxt) {
+                    self.recv.nxt = self.recv.nxt.wrapping_add(chunk.len() as u32);
+                    self.inbuf.extend(chunk);
+                    filled_gap = true;
                 }
+
+                delay_ack = skip == 0
+                    && !text.is_empty()
+                    && text.len() == payload.len()
+                    && !filled_gap
+                    && self.reassembly.is_empty();
+            }
+            fin = self.reassembly.take_fin(self.recv.nxt);
+
+            // The delivered data takes up the window, whose right edge stays in place
+            let delivered = self.recv.nxt.wrapping_sub(nxt);
+            self.recv.wnd = self.recv.wnd.saturating_sub(delivered);
+            self.recv_unacked += delivered as usize;
+
+            // Place the urgent mark once the urgent data arrived, unless it was already read
+            if let Some(up) = self.recv.up.filter(|&up| !is_after(up, self.recv.nxt)) {
+                if urgent || is_after(up, nxt) {
+                    let unread = self.recv.nxt.wrapping_sub(up) as usize;
+                    self.urgent_mark = self
+                        .inbuf
+                        .len()
+                        .checked_sub(unread)
+                        .filter(|&mark| mark > 0);
+                }
+            }
+
+            if self.read_shutdown {
+                self.inbuf.clear();
+                self.urgent_mark = None;
+                self.update_recv_window();
             }
         }
 
-        if let State::FinWait1 = self.state {
-            self.state = State::FinWait2;
+        // Process the FIN once all the data preceding it has been received
+        if fin
+            || (tcphdr.fin()
+                && !zero_window
+                && seg_seq.wrapping_add(payload.len() as u32) == self.recv.nxt)
+        {
+            let state = match self.state {
+                State::Estab => Some(State::CloseWait),
+                State::FinWait1 if fin_acked => Some(State::TimeWait),
+                State::FinWait1 => Some(State::Closing),
+                State::FinWait2 => Some(State::TimeWait),
+                _ => None,
+            };
+            if let Some(state) = state {
+                self.state = state;
+                self.recv.nxt = self.recv.nxt.wrapping_add(1);
+                delay_ack = false;
+            }
         }
-        if let State::FinWait2 = self.state {
-            if tcphdr.fin() {
-                self.state = State::TimeWait;
+
+        // Start the 2 MSL timeout when entering TIME-WAIT
+        if let (State::TimeWait, None) = (&self.state, self.timers.time_wait) {
+            self.timers.restart_time_wait(Instant::now());
+        }
+
+        // Delay the acknowledgment, unless a second full-sized segment is being acknowledged or
+        // a congestion mark is to be echoed. (RFC 9293 - Section 3.8.6.3)
+        if seg_len > 0 {
+            self.recv_seg_max = cmp::max(self.recv_seg_max, payload.len());
+            if delay_ack
+                && !marked
+                && self.recv_unacked < 2 * self.recv_seg_max
+                && !self.timers.ack_delay.is_zero()
+            {
+                let delayed_ack = Instant::now() + self.timers.ack_delay;
+                self.timers.delayed_ack.get_or_insert(delayed_ack);
+            } else {
+                self.timers.delayed_ack = Some(Instant::now());
             }
         }
 
-        if let State::Closing = self.state {
-            if seg_ack == self.send.nxt {
-                self.state = State::TimeWait;
+        // Retransmit what was lost, then send whatever the acknowledgment made room for, which
+        // piggybacks a pending acknowledgment, and send it on its own if it is due
+        self.recover(&ack)?;
+        self.transmit()?;
+        self.send_delayed_ack()?;
+
+        Ok(self.availability())
+    }
+
+    /// Detects losses from duplicate acknowledgments or the SACK scoreboard, entering a loss
+    /// recovery, and retransmits what was lost. (RFC 5681 - Section 3.2, RFC 6582 - Section 3.2
+    /// and RFC 6675 - Section 5)
+    fn recover(&mut self, ack: &Ack) -> io::Result<()> {
+        if !self.state.is_synchronized() || self.send.una == self.send.nxt {
+            return Ok(());
+        }
+
+        // A timeout already deemed everything outstanding lost, the retransmissions go on as the
+        // acknowledgments make room for them
+        if !self.scoreboard.in_recovery() && !self.scoreboard.timed_out() {
+            let lost = self.dup_acks >= DUP_THRESH
+                || (self.sack && self.scoreboard.is_lost(self.send.una, self.mss));
+            if !lost {
+                return Ok(());
+            }
+            self.scoreboard.enter_recovery(self.send.una, self.send.nxt);
+            // The window may already have been reduced by an echoed congestion mark
+            if !self.ecn.in_cwr() {
+                self.congestion_control.on_loss(ack);
             }
+
+            // Fast retransmit the first segment deemed lost, regardless of the congestion window
+            let (seq, len) = self
+                .scoreboard
+                .next_seg(self.send.una, self.mss)
+                .unwrap_or((self.send.una, self.mss));
+            self.retransmit(seq, len)?;
+        } else if self.scoreboard.in_recovery() && !self.sack && ack.acked > 0 {
+            // A partial acknowledgment, the first unacknowledged segment is lost too
+            self.retransmit(self.send.una, self.mss)?;
         }
 
-        if let State::TimeWait = self.state {
-            if tcphdr.fin() {
-                self.recv.nxt = self.recv.nxt.wrapping_add(1);
+        if self.sack || self.scoreboard.timed_out() {
+            self.retransmit_lost()?;
+        }
+        Ok(())
+    }
+
+    /// Retransmits the holes deemed lost, as the congestion window allows
+    fn retransmit_lost(&mut self) -> io::Result<()> {
+        while self.congestion_control.cwnd() >= self.pipe() + self.mss {
+            let Some((seq, len)) = self.scoreboard.next_seg(self.send.una, self.mss) else {
+                break;
+            };
+            if self.retransmit(seq, len)? == 0 {
+                break;
             }
         }
+        Ok(())
+    }
 
-        // Reset the tcp header flags regardless of the handler
-        self.reset_tcphdr_flags();
-        if let State::Estab = self.state {
-            if tcphdr.fin() {
-                self.recv.nxt = self.recv.nxt.wrapping_add(1);
-                self.state = State::CloseWait;
+    fn retransmit(&mut self, seq: u32, len: usize) -> io::Result<usize> {
+        let len = self.write(seq, len)?;
+        // A FIN takes a sequence number, it isn't retransmitted again once covered
+        let end = seq.wrapping_add(len as u32 + self.tcphdr.fin as u32);
+        self.scoreboard.on_retransmit(end);
+        Ok(len)
+    }
+
+    /// Sends a pure acknowledgment if the delayed acknowledgment timer expired
+    fn send_delayed_ack(&mut self) -> io::Result<()> {
+        if self
+            .timers
+            .delayed_ack
+            .is_some_and(|delayed_ack| delayed_ack <= Instant::now())
+        {
+            self.write(self.send.nxt, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Handles the connection timers and sends data queued by the user
+    pub(crate) fn on_tick(&mut self) -> io::Result<Available> {
+        if let State::Closed = self.state {
+            return Ok(self.availability());
+        }
+
+        if let State::TimeWait = self.state {
+            if self
+                .timers
+                .time_wait
+                .is_some_and(|time_wait| time_wait <= Instant::now())
+            {
+                self.state = State::Closed;
             }
+            return Ok(self.availability());
+        }
 
-            if tcphdr.psh() {
-                self.inbuf.write_all(&payload)?;
+        // Nothing would read what the peer sends any more, nor close the connection if its FIN
+        // never comes, so give it up after 2 MSL like TIME-WAIT
+        if let (State::FinWait2, false) = (&self.state, self.attached) {
+            let now = Instant::now();
+            if *self
+                .timers
+                .fin_wait2
+                .get_or_insert(now + self.timers.msl * 2)
+                <= now
+            {
+                self.terminate(None);
+                return Ok(self.availability());
             }
         }
 
-        if let State::CloseWait = self.state {
-            if self.outbuf.is_empty() {
-                self.tcphdr.fin = true;
-                self.state = State::LastAck;
+        if self.timers.on_expiry(Instant::now()) {
+            if self.timers.retries > self.timers.max_retries {
+                // Give up on the connection, the listener isn't told about half-open ones it
+                // didn't hand out yet
+                let error = match self.state {
+                    State::SynRcvd if !self.attached => None,
+                    _ => Some(io::ErrorKind::TimedOut),
+                };
+                self.terminate(error);
+                return Ok(self.availability());
+            }
+
+            // Go back to the earliest segment that wasn't acknowledged, everything outstanding is
+            // deemed lost and gets retransmitted as the congestion window allows. (RFC 6298 -
+            // Section 5.4 and RFC 6675 - Section 5.1) The congestion window only shrinks on the
+            // first timeout of a segment, as it is already down to a single one afterwards.
+            // (RFC 5681 - Section 3.1)
+            self.dup_acks = 0;
+            if self.state.is_synchronized() {
+                if self.timers.retries == 1 {
+                    let flight_size = self.send.nxt.wrapping_sub(self.send.una) as usize;
+                    self.congestion_control.on_rto(flight_size, self.mss);
+                }
+                self.scoreboard.on_timeout(self.send.una, self.send.nxt);
+                self.retransmit(self.send.una, self.mss)?;
+                self.retransmit_lost()?;
+            } else {
+                self.write(self.send.iss, 0)?;
             }
         }
 
-        if let State::LastAck = self.state {
-            self.state = State::Closed;
+        self.transmit()?;
+        self.send_delayed_ack()?;
+
+        // Probe a zero send window while data is waiting for it, as the window update opening it
+        // may get lost. Nothing in flight means no acknowledgment would bring the update either.
+        let unsent = self.send.nxt.wrapping_sub(self.data_start()) < self.outbuf.len() as u32;
+        if self.state.is_synchronized()
+            && self.send.wnd == 0
+            && unsent
+            && self.timers.queue.is_empty()
+        {
+            if self.timers.on_persist(Instant::now()) {
+                // An old sequence number makes the peer acknowledge with its current window
+                self.write(self.send.una.wrapping_sub(1), 0)?;
+            }
+        } else {
+            self.timers.persist = None;
         }
 
-        let parsed = self.write(payload)?;
-        self.recv.nxt = seg_seq
-            .wrapping_add(parsed as u32)
-            .wrapping_add(if tcphdr.fin() || tcphdr.syn() { 1 } else { 0 });
+        // Probe an idle connection to find out whether the peer is still there, an old sequence
+        // number makes it acknowledge. The retransmission and persist timers already keep track
+        // of a busy one. (RFC 9293 - Section 3.8.4)
+        let idle = self.timers.queue.is_empty() && self.timers.persist.is_none();
+        if matches!(self.state, State::Estab | State::CloseWait)
+            && idle
+            && self.timers.on_keepalive(Instant::now())
+        {
+            if self
+                .timers
+                .keepalive
+                .is_some_and(|keepalive| self.timers.keepalive_probes > keepalive.retries)
+            {
+                self.terminate(Some(io::ErrorKind::TimedOut));
+                return Ok(self.availability());
+            }
+            self.write(self.send.nxt.wrapping_sub(1), 0)?;
+        }
 
         Ok(self.availability())
     }
 
-    pub(crate) fn establish_connection(remote_ip: Ipv4Addr, remote_port: u16) -> io::Result<Self> {
-        let local_ip: Ipv4Addr = (std::env::var("MY_IP").unwrap()).parse().unwrap();
-        let local_port = 9182u16;
-
-        let iss = 0;
-        let wnd = 0;
+    pub(crate) fn establish_connection(
+        quad: &Quad,
+        iss: u32,
+        config: &TcpConfig,
+    ) -> io::Result<Self> {
+        // The window grows past what fits in the unscaled window field of the SYN once established
+        let wnd = cmp::min(config.recv_buffer_size, u16::MAX as usize) as u16;
         let ttl = 64;
-        let mut tcphdr = TcpHeader::new(local_port, remote_port, iss, wnd);
-        tcphdr.syn = true;
+        let tcphdr = TcpHeader::new(quad.local.1, quad.remote.1, iss, wnd);
         let iphdr = Ipv4Header::new(
-            tcphdr.header_len().wrapping_add(1) as u16,
+            tcphdr.header_len_u16(),
             ttl,
             IpNumber::TCP,
-            local_ip.octets(),
-            remote_ip.octets(),
+            quad.local.0.octets(),
+            quad.remote.0.octets(),
         )
         .expect("Invalid IP Header data");
 
-        tcphdr.checksum = tcphdr
-            .calc_checksum_ipv4(&iphdr, &[])
-            .expect("Invalid IP header");
-
         let mut connection = Connection {
             state: State::SynSent,
             send: SendSequenceSpace {
-                una: 0,
-                nxt: iss + 1,
-                wnd,
+                una: iss,
+                nxt: iss,
+                wnd: 0,
+                wnd_shift: 0,
                 iss,
-                up: false,
+                up: None,
                 wl1: 0,
                 wl2: 0,
             },
             recv: RecvSequenceSpace {
                 nxt: 0,
-                wnd: 0,
-                up: false,
+                wnd: wnd as u32,
+                wnd_shift: window_shift(config.recv_buffer_size),
+                up: None,
                 irs: 0,
             },
             iphdr,
             tcphdr,
             inbuf: VecDeque::default(),
+            urgent_mark: None,
+            recv_buffer_size: config.recv_buffer_size,
+            mtu: config.mtu,
+            mss: cmp::min(DEFAULT_MSS, config.mtu - HEADERS_LEN),
+            recv_mss: config.mtu - HEADERS_LEN,
+            window_scaling: true,
+            timestamps: Timestamps::new(iss),
+            sack: true,
+            scoreboard: Scoreboard::default(),
+            dup_acks: 0,
+            congestion_control: (config.congestion_control)(DEFAULT_MSS),
+            new_congestion_control: config.congestion_control,
+            delivery_rate: DeliveryRate::new(Instant::now()),
+            next_send: Instant::now(),
+            ecn: Ecn::new(config.ecn),
+            reassembly: Reassembly::default(),
             outbuf: VecDeque::default(),
+            fin_seq: None,
+            read_shutdown: false,
+            recv_unacked: 0,
+            recv_seg_max: 0,
+            nodelay: false,
+            timers: Timers {
+                max_retries: config.retransmission_limit,
+                ack_delay: config.ack_delay,
+                msl: config.msl,
+                ..Default::default()
+            },
+            error: None,
+            attached: false,
         };
 
-        connection.write(&[])?;
+        // Send the SYN
+        connection.write(iss, 0)?;
         Ok(connection)
     }
 }
 
+/// Reads the MSS option of a SYN segment, defaulting to 536 bytes when it is absent
+fn peer_mss(tcphdr: &TcpHeaderSlice) -> usize {
+    tcphdr
+        .options_iterator()
+        .find_map(|option| match option {
+            Ok(TcpOptionElement::MaximumSegmentSize(mss)) if mss > 0 => Some(mss as usize),
+            _ => None,
+        })
+        .unwrap_or(DEFAULT_MSS)
+}
+
+/// Reads the window scale option of a SYN segment, a shift count above 14 is taken as 14.
+/// (RFC 7323 - Section 2.3)
+fn peer_window_shift(tcphdr: &TcpHeaderSlice) -> Option<u8> {
+    tcphdr.options_iterator().find_map(|option| match option {
+        Ok(TcpOptionElement::WindowScale(shift)) => Some(cmp::min(shift, MAX_WINDOW_SHIFT)),
+        _ => None,
+    })
+}
+
+/// Checks whether a SYN segment carries the SACK-permitted option
+fn peer_sack_permitted(tcphdr: &TcpHeaderSlice) -> bool {
+    tcphdr.options_iterator().any(|option| {
+        matches!(
+            option,
+            Ok(TcpOptionElement::SelectiveAcknowledgementPermitted)
+        )
+    })
+}
+
+/// Reads the SACK blocks of a segment
+fn peer_sack_blocks(tcphdr: &TcpHeaderSlice) -> Vec<(u32, u32)> {
+    let mut blocks = Vec::new();
+    for option in tcphdr.options_iterator() {
+        if let Ok(TcpOptionElement::SelectiveAcknowledgement(first, others)) = option {
+            blocks.push(first);
+            blocks.extend(others.into_iter().flatten());
+        }
+    }
+    blocks
+}
+
+/// Reads the timestamps option of a segment, as its TSval and TSecr
+fn peer_timestamp(tcphdr: &TcpHeaderSlice) -> Option<(u32, u32)> {
+    tcphdr.options_iterator().find_map(|option| match option {
+        Ok(TcpOptionElement::Timestamp(tsval, tsecr)) => Some((tsval, tsecr)),
+        _ => None,
+    })
+}
+
+/// Smallest shift count that lets the window field advertise a receive buffer of `size` bytes
+fn window_shift(size: usize) -> u8 {
+    let mut shift = 0;
+    while size >> shift > u16::MAX as usize && shift < MAX_WINDOW_SHIFT {
+        shift += 1;
+    }
+    shift
+}
+
+/// Sends a RST in reply to a segment that no connection exists for. (RFC 9293 - Section 3.5.1 -
+/// Group 1)
+pub(crate) fn send_rst(
+    iphdr: &Ipv4HeaderSlice,
+    tcphdr: &TcpHeaderSlice,
+    payload_len: usize,
+) -> io::Result<()> {
+    reply_rst(iphdr.destination(), iphdr.source(), tcphdr, payload_len)
+}
+
+/// Sends a RST from `source` to `destination` in reply to `tcphdr`
+///
+/// If the incoming segment has the ACK bit set, the reset takes its sequence number from the ACK
+/// field of the segment; otherwise, the reset has sequence number zero and the ACK field is set to
+/// the sum of the sequence number and segment length of the incoming segment. A RST is never sent
+/// in reply to a RST.
+fn reply_rst(
+    source: [u8; 4],
+    destination: [u8; 4],
+    tcphdr: &TcpHeaderSlice,
+    payload_len: usize,
+) -> io::Result<()> {
+    if tcphdr.rst() {
+        return Ok(());
+    }
+
+    let local = (source, tcphdr.destination_port());
+    let remote = (destination, tcphdr.source_port());
+    if tcphdr.ack() {
+        write_rst(local, remote, tcphdr.acknowledgment_number(), None)
+    } else {
+        let seg_len = payload_len as u32 + if tcphdr.syn() || tcphdr.fin() { 1 } else { 0 };
+        write_rst(
+            local,
+            remote,
+            0,
+            Some(tcphdr.sequence_number().wrapping_add(seg_len)),
+        )
+    }
+}
+
+/// Writes a RST segment with the given sequence number, and acknowledgment number if any, to the
+/// nic
+fn write_rst(
+    local: ([u8; 4], u16),
+    remote: ([u8; 4], u16),
+    seq: u32,
+    ack: Option<u32>,
+) -> io::Result<()> {
+    let mut tcphdr = TcpHeader::new(local.1, remote.1, seq, 0);
+    tcphdr.rst = true;
+    if let Some(ack) = ack {
+        tcphdr.ack = true;
+        tcphdr.acknowledgment_number = ack;
+    }
+    let iphdr = Ipv4Header::new(
+        tcphdr.header_len_u16(),
+        64,
+        IpNumber::TCP,
+        local.0,
+        remote.0,
+    )
+    .expect("Invalid IP Header data");
+    tcphdr.checksum = tcphdr
+        .calc_checksum_ipv4(&iphdr, &[])
+        .expect("Invalid IP header");
+
+    let mut buf = [0u8; Ipv4Header::MAX_LEN + TcpHeader::MAX_LEN];
+    let unwritten = {
+        let mut unwritten = &mut buf[..];
+        iphdr.write(&mut unwritten)?;
+        tcphdr.write(&mut unwritten)?;
+        unwritten.len()
+    };
+    Nic::get_mut_ref()?
+        .lock()
+        .unwrap()
+        .send(&buf[..buf.len() - unwritten])?;
+    Ok(())
+}
+
 /// Checks if `x` is in the range of [`start`, `end`] exclusive.
 ///
 /// Since start and end can wrap, we have three cases:
@@ -581,7 +1962,7 @@ impl Connection {
 /// ---
 ///
 /// - Case I: `start` and `end` are equal
-/// ```
+/// ```text
 ///
 ///                                
 ///        ---------------|---------------
@@ -592,7 +1973,7 @@ impl Connection {
 /// ```
 ///
 /// - Case II: `start` and `end` are not equal and there is no wrapping:
-/// ```
+/// ```text
 ///
 ///             1         2          3
 ///        ----------|----------|----------
@@ -606,7 +1987,7 @@ impl Connection {
 /// ---
 ///
 /// - Case III: `start` and `end` are not equal and there is wrapping:
-/// ```
+/// ```text
 ///
 ///             1         2          3
 ///        ----------|----------|----------
@@ -624,10 +2005,7 @@ fn is_in_range_wrap(start: u32, x: u32, end: u32) -> bool {
     }
 }
 
-fn is_duplicate(una: u32, ack: u32, nxt: u32) -> bool {
-    match una.cmp(&nxt) {
-        Ordering::Equal => true,
-        Ordering::Less => ack < una,
-        Ordering::Greater => ack > nxt && ack < una,
-    }
+/// Checks if sequence number `a` comes after `b`, taking wrapping into account
+pub(crate) fn is_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
 }
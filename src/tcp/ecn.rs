@@ -0,0 +1,89 @@
+use etherparse::{Ipv4Ecn, TcpHeaderSlice};
+
+use super::is_after;
+
+/// ECN-Capable Transport codepoint our new data segments are marked with (ECT(0))
+pub(super) const ECT0: Ipv4Ecn = Ipv4Ecn::TWO;
+/// Congestion Experienced codepoint, set by routers instead of dropping an ECN-capable packet
+const CE: Ipv4Ecn = Ipv4Ecn::TRHEE;
+
+/// State of Explicit Congestion Notification. (RFC 3168 - Section 6.1)
+///
+/// The receiver echoes a congestion mark with ECE on its segments until the sender tells it,
+/// with CWR, that its congestion window was reduced.
+#[derive(Debug)]
+pub(super) struct Ecn {
+    /// both sides agreed on ECN, it is requested in our SYN until the peer answers
+    pub(super) enabled: bool,
+    /// a congestion mark was received, ECE is set on our segments until the peer sends CWR
+    echo: bool,
+    /// the congestion window was reduced, CWR is set on the next new data segment
+    cwr: bool,
+    /// `SND.NXT` when the congestion window was last reduced, further ECE are ignored until it is
+    /// acknowledged, as the window is reduced at most once per round trip
+    recovery_point: Option<u32>,
+}
+
+impl Ecn {
+    pub(super) fn new(enabled: bool) -> Self {
+        Ecn {
+            enabled,
+            echo: false,
+            cwr: false,
+            recovery_point: None,
+        }
+    }
+
+    /// Checks whether a SYN, or the SYN-ACK answering ours, agrees on ECN. An ECN-setup SYN has
+    /// ECE and CWR set, and the SYN-ACK agreeing to it only ECE. (RFC 3168 - Section 6.1.1)
+    pub(super) fn is_setup(tcphdr: &TcpHeaderSlice) -> bool {
+        tcphdr.ece() && tcphdr.cwr() != tcphdr.ack()
+    }
+
+    /// Records the congestion mark and CWR of an acceptable segment. A CWR only stops the echo of
+    /// the marks that came before it.
+    ///
+    /// Returns whether the segment was marked
+    pub(super) fn on_receive(&mut self, ecn: Ipv4Ecn, cwr: bool) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if cwr {
+            self.echo = false;
+        }
+        let marked = ecn == CE;
+        self.echo |= marked;
+        marked
+    }
+
+    /// Checks whether our segments set ECE
+    pub(super) fn echo(&self) -> bool {
+        self.enabled && self.echo
+    }
+
+    /// Checks whether the congestion window was reduced less than a round trip ago
+    pub(super) fn in_cwr(&self) -> bool {
+        self.recovery_point.is_some()
+    }
+
+    /// Records a reduction of the congestion window in response to ECE, with `nxt` as `SND.NXT`
+    pub(super) fn enter_cwr(&mut self, nxt: u32) {
+        self.recovery_point = Some(nxt);
+        self.cwr = true;
+    }
+
+    /// Ends the round trip following a reduction once `una` acknowledges everything sent before it
+    pub(super) fn on_ack(&mut self, una: u32) {
+        if self
+            .recovery_point
+            .is_some_and(|recovery_point| !is_after(recovery_point, una))
+        {
+            self.recovery_point = None;
+        }
+    }
+
+    /// Takes the pending CWR, for a new data segment
+    pub(super) fn take_cwr(&mut self) -> bool {
+        std::mem::take(&mut self.cwr)
+    }
+}
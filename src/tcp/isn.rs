@@ -0,0 +1,116 @@
+use std::{
+    collections::hash_map::{DefaultHasher, RandomState},
+    hash::{BuildHasher, BuildHasherDefault},
+    time::Instant,
+};
+
+use crate::Quad;
+
+/// Clock ticks the sequence numbers of a seeded generator advance by between two connections,
+/// i.e. a second worth of the 4 microseconds timer
+const SEEDED_TICKS: u32 = 250_000;
+
+/// Generator of initial sequence numbers. (RFC 6528 - Section 3)
+///
+/// ```text
+///  ISN = M + F(localip, localport, remoteip, remoteport, secretkey)
+/// ```
+///
+/// Where `M` is a 4 microseconds timer and `F` a keyed hash of the connection identifiers, so
+/// sequence numbers are unpredictable to off-path attackers yet keep increasing for a given quad.
+#[derive(Debug)]
+pub(crate) struct IsnGenerator {
+    /// random secret key of `F`
+    key: RandomState,
+    /// seed replacing the secret key, for reproducible sequence numbers
+    seed: Option<u64>,
+    /// origin of the `M` timer
+    start: Instant,
+    /// number of sequence numbers generated so far
+    count: u32,
+}
+
+impl Default for IsnGenerator {
+    fn default() -> Self {
+        IsnGenerator {
+            key: RandomState::new(),
+            seed: None,
+            start: Instant::now(),
+            count: 0,
+        }
+    }
+}
+
+impl IsnGenerator {
+    /// Creates a generator whose sequence numbers only depend on `seed` and the order the
+    /// connections are created in. The `M` timer then counts the connections rather than time.
+    pub(crate) fn with_seed(seed: u64) -> Self {
+        IsnGenerator {
+            seed: Some(seed),
+            ..Default::default()
+        }
+    }
+
+    /// Generates the initial sequence number of a connection
+    pub(crate) fn generate(&mut self, quad: &Quad) -> u32 {
+        let (m, f) = match self.seed {
+            None => (
+                (self.start.elapsed().as_micros() / 4) as u32,
+                self.key.hash_one(quad),
+            ),
+            Some(seed) => (
+                self.count.wrapping_mul(SEEDED_TICKS),
+                BuildHasherDefault::<DefaultHasher>::default().hash_one((seed, quad)),
+            ),
+        };
+        self.count = self.count.wrapping_add(1);
+
+        m.wrapping_add(f as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn quad(remote_port: u16) -> Quad {
+        Quad {
+            local: (Ipv4Addr::new(10, 0, 0, 1), 8080),
+            remote: (Ipv4Addr::new(10, 0, 0, 2), remote_port),
+        }
+    }
+
+    #[test]
+    fn seeded_generators_are_reproducible() {
+        let mut first = IsnGenerator::with_seed(42);
+        let mut second = IsnGenerator::with_seed(42);
+        for port in [40000, 40001, 40000] {
+            assert_eq!(first.generate(&quad(port)), second.generate(&quad(port)));
+        }
+
+        let mut other = IsnGenerator::with_seed(43);
+        assert_ne!(
+            IsnGenerator::with_seed(42).generate(&quad(40000)),
+            other.generate(&quad(40000))
+        );
+    }
+
+    #[test]
+    fn seeded_isn_advances_per_connection() {
+        let mut generator = IsnGenerator::with_seed(42);
+        let first = generator.generate(&quad(40000));
+        generator.generate(&quad(40001));
+        let third = generator.generate(&quad(40000));
+        assert_eq!(third.wrapping_sub(first), 2 * SEEDED_TICKS);
+    }
+
+    #[test]
+    fn isn_depends_on_the_quad() {
+        let mut generator = IsnGenerator::with_seed(42);
+        let first = generator.generate(&quad(40000));
+        let second = generator.generate(&quad(40001));
+        assert_ne!(second.wrapping_sub(first), SEEDED_TICKS);
+    }
+}
@@ -0,0 +1,213 @@
+use std::{cmp, collections::BTreeMap, time::Instant};
+
+use super::is_after;
+use crate::congestion::RateSample;
+
+/// Delivery rate estimation. (draft-cheng-iccrg-delivery-rate-estimation)
+///
+/// Every segment sent takes a snapshot of how much was delivered so far, so that its delivery
+/// gives the data delivered in between along with how long it took.
+#[derive(Debug)]
+pub(super) struct DeliveryRate {
+    /// segments in flight, keyed by their sequence number
+    segments: BTreeMap<u32, Segment>,
+    /// bytes delivered so far (C.delivered)
+    delivered: u64,
+    /// time `delivered` last grew at (C.delivered_time)
+    delivered_time: Instant,
+    /// send time of the most recently delivered segment (C.first_sent_time)
+    first_sent_time: Instant,
+    /// value `delivered` reaches once the data sent while application-limited is delivered, if
+    /// the connection is application-limited (C.app_limited)
+    app_limited: Option<u64>,
+}
+
+/// Snapshot of the delivery state when a segment was sent
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    /// length in sequence space
+    len: u32,
+    /// time the segment was sent at
+    sent_time: Instant,
+    /// (P.delivered)
+    delivered: u64,
+    /// (P.delivered_time)
+    delivered_time: Instant,
+    /// (P.first_sent_time)
+    first_sent_time: Instant,
+    /// (P.is_app_limited)
+    app_limited: bool,
+}
+
+impl DeliveryRate {
+    pub(super) fn new(now: Instant) -> Self {
+        DeliveryRate {
+            segments: BTreeMap::new(),
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            app_limited: None,
+        }
+    }
+
+    pub(super) fn delivered(&self) -> u64 {
+        self.delivered
+    }
+
+    /// Records a segment of `len` sequence numbers starting at `seq` that was just sent. The
+    /// intervals restart when nothing was in flight. (Section 3.2)
+    pub(super) fn on_send(&mut self, seq: u32, len: u32, now: Instant) {
+        if self.segments.is_empty() {
+            self.first_sent_time = now;
+            self.delivered_time = now;
+        }
+        self.segments.insert(
+            seq,
+            Segment {
+                len,
+                sent_time: now,
+                delivered: self.delivered,
+                delivered_time: self.delivered_time,
+                first_sent_time: self.first_sent_time,
+                app_limited: self.app_limited.is_some(),
+            },
+        );
+    }
+
+    /// Marks the connection as application-limited, i.e. it has nothing to send while the
+    /// congestion window would allow it, with `pipe` bytes in flight. (Section 3.4)
+    pub(super) fn on_app_limited(&mut self, pipe: usize) {
+        self.app_limited = Some(cmp::max(self.delivered + pipe as u64, 1));
+    }
+
+    /// Delivers the segments acknowledged by `una` or SACKed, and samples the delivery rate from
+    /// the most recently sent of them. (Section 3.3)
+    pub(super) fn on_ack(
+        &mut self,
+        una: u32,
+        is_sacked: impl Fn(u32, u32) -> bool,
+        now: Instant,
+    ) -> Option<RateSample> {
+        let mut newest: Option<Segment> = None;
+        let mut delivered = 0;
+        self.segments.retain(|&seq, segment| {
+            let end = seq.wrapping_add(segment.len);
+            if is_after(end, una) && !is_sacked(seq, end) {
+                return true;
+            }
+            delivered += segment.len as u64;
+            if newest
+                .as_ref()
+                .is_none_or(|newest| segment.delivered >= newest.delivered)
+            {
+                newest = Some(*segment);
+            }
+            false
+        });
+        let newest = newest?;
+
+        self.delivered += delivered;
+        self.delivered_time = now;
+        self.first_sent_time = newest.sent_time;
+        if self.app_limited.is_some_and(|end| self.delivered > end) {
+            self.app_limited = None;
+        }
+
+        // The data may be sent faster than it is acknowledged, or the other way around,
+        // the slower of the two phases bounds the rate
+        let send_elapsed = newest.sent_time - newest.first_sent_time;
+        let ack_elapsed = self.delivered_time - newest.delivered_time;
+        Some(RateSample {
+            delivered: self.delivered - newest.delivered,
+            interval: cmp::max(send_elapsed, ack_elapsed),
+            prior_delivered: newest.delivered,
+            app_limited: newest.app_limited,
+        })
+        .filter(|sample| !sample.interval.is_zero())
+    }
+
+    /// Drops the segments in flight
+    pub(super) fn clear(&mut self) {
+        self.segments.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn samples_the_most_recently_sent_segment() {
+        let start = Instant::now();
+        let mut rate = DeliveryRate::new(start);
+        for i in 0..3 {
+            rate.on_send(i * 1000, 1000, start + ms(i as u64));
+        }
+        assert!(rate.on_ack(0, |_, _| false, start + ms(5)).is_none());
+
+        let sample = rate.on_ack(1000, |_, _| false, start + ms(10)).unwrap();
+        assert_eq!(sample.delivered, 1000);
+        assert_eq!(sample.prior_delivered, 0);
+        assert_eq!(sample.interval, ms(10));
+        assert_eq!(sample.delivery_rate(), 100_000);
+
+        // The acknowledgment phase is the longer one, since the first delivery
+        let sample = rate.on_ack(3000, |_, _| false, start + ms(12)).unwrap();
+        assert_eq!(rate.delivered(), 3000);
+        assert_eq!(sample.delivered, 3000);
+        assert_eq!(sample.interval, ms(12));
+    }
+
+    #[test]
+    fn sacked_segments_are_delivered() {
+        let start = Instant::now();
+        let mut rate = DeliveryRate::new(start);
+        rate.on_send(u32::MAX - 999, 1000, start);
+        rate.on_send(0, 1000, start + ms(1));
+
+        let sample = rate
+            .on_ack(
+                u32::MAX - 999,
+                |seq, end| seq == 0 && end == 1000,
+                start + ms(10),
+            )
+            .unwrap();
+        assert_eq!(sample.delivered, 1000);
+        assert_eq!(rate.delivered(), 1000);
+
+        // Only the segment left is delivered by the cumulative acknowledgment
+        rate.on_ack(1000, |_, _| false, start + ms(20)).unwrap();
+        assert_eq!(rate.delivered(), 2000);
+    }
+
+    #[test]
+    fn app_limited_samples_are_flagged() {
+        let start = Instant::now();
+        let mut rate = DeliveryRate::new(start);
+        rate.on_app_limited(0);
+        rate.on_send(0, 1000, start);
+        let sample = rate.on_ack(1000, |_, _| false, start + ms(10)).unwrap();
+        assert!(sample.app_limited);
+
+        // The data sent while application-limited was delivered
+        rate.on_send(1000, 1000, start + ms(10));
+        let sample = rate.on_ack(2000, |_, _| false, start + ms(20)).unwrap();
+        assert!(!sample.app_limited);
+        assert_eq!(sample.prior_delivered, 1000);
+    }
+
+    #[test]
+    fn empty_intervals_give_no_sample() {
+        let start = Instant::now();
+        let mut rate = DeliveryRate::new(start);
+        rate.on_send(0, 1000, start);
+        assert!(rate.on_ack(1000, |_, _| false, start).is_none());
+        assert_eq!(rate.delivered(), 1000);
+    }
+}
@@ -0,0 +1,228 @@
+use std::cmp;
+
+use super::{is_after, is_in_range_wrap};
+
+/// Out-of-order segments held until the gap before them is filled. (RFC 9293 - SHLD-31)
+///
+/// The held data never overlaps itself and is kept sorted by sequence number. Since only data
+/// within the receive window is stored, the order relative to `RCV.NXT` is well defined even when
+/// the sequence numbers wrap.
+#[derive(Debug, Default)]
+pub(super) struct Reassembly {
+    /// chunks of held data and their starting sequence numbers
+    chunks: Vec<(u32, Vec<u8>)>,
+    /// sequence number of a FIN that arrived out of order
+    fin: Option<u32>,
+    /// sequence number of the most recently held segment
+    last: Option<u32>,
+}
+
+impl Reassembly {
+    /// Holds `data` starting at `seq`, which comes after `nxt`. The parts overlapping data that is
+    /// already held are trimmed off.
+    pub(super) fn insert(&mut self, nxt: u32, seq: u32, data: &[u8]) {
+        let offset = |seq: u32| seq.wrapping_sub(nxt) as usize;
+        let seg_start = offset(seq);
+        let seg_end = seg_start + data.len();
+        if !data.is_empty() {
+            self.last = Some(seq);
+        }
+
+        // Find the gaps in the held data that the segment fills
+        let mut gaps = Vec::new();
+        let mut start = seg_start;
+        for (chunk_seq, chunk) in &self.chunks {
+            let chunk_start = offset(*chunk_seq);
+            let chunk_end = chunk_start + chunk.len();
+            if chunk_end <= start {
+                continue;
+            }
+            if chunk_start >= seg_end {
+                break;
+            }
+            if chunk_start > start {
+                gaps.push((start, chunk_start));
+            }
+            start = cmp::max(start, chunk_end);
+        }
+        if start < seg_end {
+            gaps.push((start, seg_end));
+        }
+
+        for (start, end) in gaps {
+            let at = self
+                .chunks
+                .partition_point(|(chunk_seq, _)| offset(*chunk_seq) < start);
+            self.chunks.insert(
+                at,
+                (
+                    nxt.wrapping_add(start as u32),
+                    data[start - seg_start..end - seg_start].to_vec(),
+                ),
+            );
+        }
+    }
+
+    /// Remembers a FIN at `seq` that arrived before the data preceding it.
+    pub(super) fn insert_fin(&mut self, seq: u32) {
+        self.fin = Some(seq);
+    }
+
+    /// Removes and returns the held data that became contiguous with `nxt`.
+    pub(super) fn pop(&mut self, nxt: u32) -> Option<Vec<u8>> {
+        while let Some((seq, _)) = self.chunks.first() {
+            if is_after(*seq, nxt) {
+                // There is still a gap before the first chunk
+                return None;
+            }
+
+            let (seq, mut chunk) = self.chunks.remove(0);
+            let skip = nxt.wrapping_sub(seq) as usize;
+            if skip < chunk.len() {
+                drop(chunk.drain(..skip));
+                return Some(chunk);
+            }
+        }
+        None
+    }
+
+    /// Checks whether the held FIN is the next thing to be received.
+    pub(super) fn take_fin(&mut self, nxt: u32) -> bool {
+        if self.fin == Some(nxt) {
+            self.fin = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Checks whether no data is being held
+    pub(super) fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Ranges of held data to report in at most `max` SACK blocks, the one holding the most
+    /// recently received segment first. (RFC 2018 - Section 4)
+    pub(super) fn sack_blocks(&self, max: usize) -> Vec<(u32, u32)> {
+        let mut blocks: Vec<(u32, u32)> = Vec::new();
+        for (seq, chunk) in &self.chunks {
+            let end = seq.wrapping_add(chunk.len() as u32);
+            match blocks.last_mut() {
+                Some((_, last_end)) if last_end == seq => *last_end = end,
+                _ => blocks.push((*seq, end)),
+            }
+        }
+
+        if let Some(last) = self.last {
+            if let Some(at) = blocks
+                .iter()
+                .position(|&(start, end)| is_in_range_wrap(start.wrapping_sub(1), last, end))
+            {
+                let block = blocks.remove(at);
+                blocks.insert(0, block);
+            }
+        }
+        blocks.truncate(max);
+        blocks
+    }
+
+    /// Drops everything being held
+    pub(super) fn clear(&mut self) {
+        self.chunks.clear();
+        self.fin = None;
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_trims_overlapping_data() {
+        let mut reassembly = Reassembly::default();
+        reassembly.insert(100, 110, &[1; 10]);
+        reassembly.insert(100, 105, &[2; 20]);
+
+        assert_eq!(reassembly.pop(100), None);
+        assert_eq!(reassembly.pop(105), Some(vec![2; 5]));
+        assert_eq!(reassembly.pop(110), Some(vec![1; 10]));
+        assert_eq!(reassembly.pop(120), Some(vec![2; 5]));
+        assert!(reassembly.is_empty());
+    }
+
+    #[test]
+    fn insert_ignores_held_data() {
+        let mut reassembly = Reassembly::default();
+        reassembly.insert(0, 10, &[1; 10]);
+        reassembly.insert(0, 12, &[2; 4]);
+
+        assert_eq!(reassembly.sack_blocks(4), vec![(10, 20)]);
+        assert_eq!(reassembly.pop(10), Some(vec![1; 10]));
+        assert!(reassembly.is_empty());
+    }
+
+    #[test]
+    fn pop_skips_received_data() {
+        let mut reassembly = Reassembly::default();
+        reassembly.insert(0, 10, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        reassembly.insert(0, 20, &[11; 10]);
+
+        assert_eq!(reassembly.pop(15), Some(vec![6, 7, 8, 9, 10]));
+        // A chunk entirely behind `nxt` is dropped
+        reassembly.insert(0, 30, &[21; 10]);
+        assert_eq!(reassembly.pop(35), Some(vec![21; 5]));
+        assert!(reassembly.is_empty());
+    }
+
+    #[test]
+    fn insert_wraps_around() {
+        let nxt = u32::MAX - 5;
+        let mut reassembly = Reassembly::default();
+        reassembly.insert(nxt, u32::MAX - 2, &[1; 6]);
+        reassembly.insert(nxt, 2, &[2; 3]);
+
+        assert_eq!(reassembly.sack_blocks(4), vec![(u32::MAX - 2, 5)]);
+        assert_eq!(reassembly.pop(nxt), None);
+        assert_eq!(reassembly.pop(u32::MAX - 2), Some(vec![1; 6]));
+        assert_eq!(reassembly.pop(3), Some(vec![2; 2]));
+    }
+
+    #[test]
+    fn sack_blocks_report_most_recent_first() {
+        let mut reassembly = Reassembly::default();
+        reassembly.insert(0, 10, &[0; 10]);
+        reassembly.insert(0, 30, &[0; 10]);
+        reassembly.insert(0, 50, &[0; 10]);
+        reassembly.insert(0, 20, &[0; 5]);
+
+        assert_eq!(
+            reassembly.sack_blocks(4),
+            vec![(10, 25), (30, 40), (50, 60)]
+        );
+        assert_eq!(reassembly.sack_blocks(2), vec![(10, 25), (30, 40)]);
+        assert_eq!(reassembly.sack_blocks(0), vec![]);
+    }
+
+    #[test]
+    fn fin_is_taken_once_reached() {
+        let mut reassembly = Reassembly::default();
+        reassembly.insert_fin(10);
+
+        assert!(!reassembly.take_fin(9));
+        assert!(reassembly.take_fin(10));
+        assert!(!reassembly.take_fin(10));
+    }
+
+    #[test]
+    fn clear_drops_everything() {
+        let mut reassembly = Reassembly::default();
+        reassembly.insert(0, 10, &[0; 10]);
+        reassembly.insert_fin(20);
+        reassembly.clear();
+
+        assert!(reassembly.is_empty());
+        assert!(!reassembly.take_fin(20));
+        assert_eq!(reassembly.sack_blocks(4), vec![]);
+    }
+}
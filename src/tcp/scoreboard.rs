@@ -0,0 +1,328 @@
+use std::{cmp, iter};
+
+use super::is_after;
+
+/// Number of discontiguous SACKed ranges above a hole after which it is deemed lost, it takes
+/// one more segment than that many MSS of SACKed bytes otherwise. (RFC 6675 - Section 2)
+pub(super) const DUP_THRESH: usize = 3;
+
+/// Data the peer selectively acknowledged, driving the loss recovery. (RFC 6675)
+///
+/// The SACKed ranges only cover data above `SND.UNA` that was sent, so their order relative to
+/// `SND.UNA` is well defined even when the sequence numbers wrap.
+#[derive(Debug, Default)]
+pub(super) struct Scoreboard {
+    /// SACKed ranges as (start, end), sorted and disjoint
+    blocks: Vec<(u32, u32)>,
+    /// end of the last retransmission of the current recovery (HighRxt)
+    high_rxt: u32,
+    /// `SND.NXT` when the current recovery started (RecoveryPoint), if there is one
+    recovery_point: Option<u32>,
+    /// `SND.NXT` when the retransmission timer last expired, everything before it that wasn't
+    /// acknowledged since is deemed lost
+    timeout_point: Option<u32>,
+}
+
+impl Scoreboard {
+    /// Records the SACK blocks of an acknowledgment, and forgets what `una` acknowledges. The
+    /// recovery ends once all the data outstanding when it started is acknowledged.
+    pub(super) fn on_ack(&mut self, una: u32, nxt: u32, blocks: &[(u32, u32)]) {
+        self.blocks.retain_mut(|(start, end)| {
+            if is_after(una, *start) {
+                *start = una;
+            }
+            is_after(*end, una)
+        });
+
+        // Blocks outside the outstanding data are either bogus or report duplicates, which
+        // aren't used
+        self.blocks.extend(blocks.iter().filter(|(start, end)| {
+            is_after(*start, una) && is_after(*end, *start) && !is_after(*end, nxt)
+        }));
+        self.blocks
+            .sort_by_key(|(start, _)| start.wrapping_sub(una));
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(self.blocks.len());
+        for (start, end) in self.blocks.drain(..) {
+            match merged.last_mut() {
+                Some((_, last_end)) if !is_after(start, *last_end) => {
+                    if is_after(end, *last_end) {
+                        *last_end = end;
+                    }
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+        self.blocks = merged;
+
+        if self
+            .recovery_point
+            .is_some_and(|recovery_point| !is_after(recovery_point, una))
+        {
+            self.recovery_point = None;
+        }
+        if self
+            .timeout_point
+            .is_some_and(|timeout_point| !is_after(timeout_point, una))
+        {
+            self.timeout_point = None;
+        }
+    }
+
+    /// Checks whether the data at `seq` is deemed lost, as it was sent before a timeout or enough
+    /// was SACKed above it. (RFC 6675 - Section 4 - IsLost)
+    pub(super) fn is_lost(&self, seq: u32, mss: usize) -> bool {
+        if self
+            .timeout_point
+            .is_some_and(|timeout_point| is_after(timeout_point, seq))
+        {
+            return true;
+        }
+        let mut ranges = 0;
+        let mut sacked = 0;
+        for &(start, end) in self.blocks.iter().filter(|(_, end)| is_after(*end, seq)) {
+            let start = if is_after(start, seq) { start } else { seq };
+            ranges += 1;
+            sacked += end.wrapping_sub(start) as usize;
+        }
+        ranges >= DUP_THRESH || sacked > (DUP_THRESH - 1) * mss
+    }
+
+    /// Checks whether the range from `start` to `end` was entirely SACKed
+    pub(super) fn is_sacked(&self, start: u32, end: u32) -> bool {
+        self.blocks.iter().any(|&(block_start, block_end)| {
+            !is_after(block_start, start) && !is_after(end, block_end)
+        })
+    }
+
+    /// Estimates the bytes in flight between `una` and `nxt`. The SACKed data left the network,
+    /// and so did the holes deemed lost, unless they were retransmitted. (RFC 6675 - Section 4 -
+    /// SetPipe)
+    pub(super) fn pipe(&self, una: u32, nxt: u32, mss: usize) -> usize {
+        let mut pipe = 0;
+        let mut start = una;
+        for &(block_start, block_end) in self.blocks.iter().chain(iter::once(&(nxt, nxt))) {
+            if is_after(block_start, start) {
+                pipe += self.hole_pipe(start, block_start, mss);
+            }
+            if is_after(block_end, start) {
+                start = block_end;
+            }
+        }
+        pipe
+    }
+
+    /// Estimates the bytes in flight in the hole from `start` to `end`
+    fn hole_pipe(&self, start: u32, end: u32, mss: usize) -> usize {
+        // The data sent after a timeout isn't deemed lost by it
+        if let Some(timeout_point) = self
+            .timeout_point
+            .filter(|&timeout_point| is_after(timeout_point, start) && is_after(end, timeout_point))
+        {
+            return self.hole_pipe(start, timeout_point, mss)
+                + self.hole_pipe(timeout_point, end, mss);
+        }
+
+        let len = end.wrapping_sub(start) as usize;
+        if !self.is_lost(start, mss) {
+            len
+        } else if (self.in_recovery() || self.timed_out()) && is_after(self.high_rxt, start) {
+            cmp::min(self.high_rxt.wrapping_sub(start) as usize, len)
+        } else {
+            0
+        }
+    }
+
+    pub(super) fn in_recovery(&self) -> bool {
+        self.recovery_point.is_some()
+    }
+
+    /// Starts a loss recovery, lasting until everything sent so far is acknowledged
+    pub(super) fn enter_recovery(&mut self, una: u32, nxt: u32) {
+        self.recovery_point = Some(nxt);
+        self.high_rxt = una;
+    }
+
+    /// Checks whether the data outstanding when the retransmission timer last expired is still
+    /// being retransmitted
+    pub(super) fn timed_out(&self) -> bool {
+        self.timeout_point.is_some()
+    }
+
+    /// Deems everything outstanding lost after the retransmission timer expired, and forgets what
+    /// was SACKed as the peer may have dropped it. (RFC 2018 - Section 8 and RFC 6675 - Section
+    /// 5.1) The retransmissions start over from `una`, and any loss recovery ends.
+    pub(super) fn on_timeout(&mut self, una: u32, nxt: u32) {
+        self.blocks.clear();
+        self.recovery_point = None;
+        self.timeout_point = Some(nxt);
+        self.high_rxt = una;
+    }
+
+    /// Picks the next range to retransmit, i.e. the first hole after the last retransmission if
+    /// it is deemed lost, and at most `mss` bytes long. (RFC 6675 - Section 4 - NextSeg - Rule 1)
+    /// After a timeout, the data above the SACKed ranges that was sent before it is lost too.
+    pub(super) fn next_seg(&self, una: u32, mss: usize) -> Option<(u32, usize)> {
+        let mut start = if is_after(self.high_rxt, una) {
+            self.high_rxt
+        } else {
+            una
+        };
+        for &(block_start, block_end) in &self.blocks {
+            if !is_after(block_end, start) {
+                continue;
+            }
+            if is_after(block_start, start) {
+                // Holes further up have even less SACKed above them
+                if !self.is_lost(start, mss) {
+                    return None;
+                }
+                let len = block_start.wrapping_sub(start) as usize;
+                return Some((start, cmp::min(len, mss)));
+            }
+            start = block_end;
+        }
+        match self.timeout_point {
+            Some(timeout_point) if is_after(timeout_point, start) => {
+                let len = timeout_point.wrapping_sub(start) as usize;
+                Some((start, cmp::min(len, mss)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Records the end of a retransmission of the current recovery
+    pub(super) fn on_retransmit(&mut self, end: u32) {
+        self.high_rxt = end;
+    }
+
+    /// Forgets everything that was SACKed and ends the recovery, if any
+    pub(super) fn clear(&mut self) {
+        self.blocks.clear();
+        self.recovery_point = None;
+        self.timeout_point = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: usize = 100;
+    /// `SND.UNA` of the tests, with and without the sequence numbers wrapping past `nxt`
+    const BASES: [u32; 2] = [1000, u32::MAX - 150];
+
+    /// Scoreboard of six segments sent from `una`, where every other one was SACKed
+    fn sacked_every_other(una: u32) -> Scoreboard {
+        let at = |offset: u32| una.wrapping_add(offset);
+        let mut scoreboard = Scoreboard::default();
+        scoreboard.on_ack(
+            una,
+            at(600),
+            &[(at(500), at(600)), (at(100), at(200)), (at(300), at(400))],
+        );
+        scoreboard
+    }
+
+    #[test]
+    fn on_ack_merges_and_filters_blocks() {
+        for una in BASES {
+            let at = |offset: u32| una.wrapping_add(offset);
+            let mut scoreboard = Scoreboard::default();
+            scoreboard.on_ack(
+                una,
+                at(600),
+                &[
+                    (at(100), at(200)),
+                    (at(150), at(300)),
+                    // Past SND.NXT, and below SND.UNA
+                    (at(500), at(700)),
+                    (una.wrapping_sub(100), una),
+                ],
+            );
+            assert_eq!(scoreboard.blocks, vec![(at(100), at(300))]);
+
+            // Acknowledging part of a block trims it
+            scoreboard.on_ack(at(150), at(600), &[]);
+            assert_eq!(scoreboard.blocks, vec![(at(150), at(300))]);
+            assert!(scoreboard.is_sacked(at(200), at(300)));
+            assert!(!scoreboard.is_sacked(at(200), at(350)));
+        }
+    }
+
+    #[test]
+    fn is_lost_counts_ranges_and_bytes() {
+        for una in BASES {
+            let at = |offset: u32| una.wrapping_add(offset);
+            let scoreboard = sacked_every_other(una);
+            assert!(scoreboard.is_lost(una, MSS));
+            // Two ranges and 200 bytes above
+            assert!(!scoreboard.is_lost(at(200), MSS));
+
+            let mut scoreboard = Scoreboard::default();
+            scoreboard.on_ack(una, at(600), &[(at(100), at(300))]);
+            assert!(!scoreboard.is_lost(una, MSS));
+            scoreboard.on_ack(una, at(600), &[(at(300), at(301))]);
+            assert!(scoreboard.is_lost(una, MSS));
+        }
+    }
+
+    #[test]
+    fn pipe_leaves_out_sacked_and_lost_data() {
+        for una in BASES {
+            let at = |offset: u32| una.wrapping_add(offset);
+            let mut scoreboard = Scoreboard::default();
+            assert_eq!(scoreboard.pipe(una, at(600), MSS), 600);
+
+            scoreboard = sacked_every_other(una);
+            scoreboard.enter_recovery(una, at(600));
+            assert_eq!(scoreboard.pipe(una, at(600), MSS), 200);
+            scoreboard.on_retransmit(at(100));
+            assert_eq!(scoreboard.pipe(una, at(600), MSS), 300);
+        }
+    }
+
+    #[test]
+    fn next_seg_walks_lost_holes() {
+        for una in BASES {
+            let at = |offset: u32| una.wrapping_add(offset);
+            let mut scoreboard = sacked_every_other(una);
+            scoreboard.enter_recovery(una, at(600));
+            assert_eq!(scoreboard.next_seg(una, MSS), Some((una, MSS)));
+            assert_eq!(scoreboard.next_seg(una, 40), Some((una, 40)));
+
+            // The next hole isn't deemed lost yet
+            scoreboard.on_retransmit(at(100));
+            assert_eq!(scoreboard.next_seg(una, MSS), None);
+
+            // The recovery ends once everything outstanding when it started is acknowledged
+            scoreboard.on_ack(at(600), at(600), &[]);
+            assert!(!scoreboard.in_recovery());
+        }
+    }
+
+    #[test]
+    fn timeout_deems_everything_lost() {
+        for una in BASES {
+            let at = |offset: u32| una.wrapping_add(offset);
+            let mut scoreboard = sacked_every_other(una);
+            scoreboard.on_timeout(una, at(600));
+            assert!(scoreboard.timed_out());
+            assert!(!scoreboard.in_recovery());
+            assert!(scoreboard.is_lost(at(500), MSS));
+            assert_eq!(scoreboard.pipe(una, at(600), MSS), 0);
+            // Data sent after the timeout isn't lost
+            assert_eq!(scoreboard.pipe(una, at(700), MSS), 100);
+
+            for offset in (0..600).step_by(MSS) {
+                assert_eq!(scoreboard.next_seg(una, MSS), Some((at(offset), MSS)));
+                scoreboard.on_retransmit(at(offset + 100));
+                assert_eq!(scoreboard.pipe(una, at(600), MSS), offset as usize + 100);
+            }
+            assert_eq!(scoreboard.next_seg(una, MSS), None);
+
+            scoreboard.on_ack(at(600), at(700), &[]);
+            assert!(!scoreboard.timed_out());
+            assert_eq!(scoreboard.pipe(at(600), at(700), MSS), 100);
+        }
+    }
+}
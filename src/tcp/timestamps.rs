@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+use super::is_after;
+
+/// Length of the timestamps option, along with the two NOPs aligning it
+pub(super) const TIMESTAMPS_LEN: usize = 12;
+/// Idle time after which `TS.Recent` is no longer trusted to reject old duplicates.
+/// (RFC 7323 - Section 5.5)
+const RECENT_LIFETIME: Duration = Duration::from_secs(24 * 24 * 60 * 60);
+
+/// State of the timestamps option. (RFC 7323 - Section 3 and 4)
+///
+/// Our timestamp clock ticks every millisecond, starting at a per-connection offset so that it
+/// doesn't leak how long the stack has been running. (RFC 7323 - Section 5.4)
+#[derive(Debug)]
+pub(super) struct Timestamps {
+    /// both sides sent the option, it is offered in our SYN until the peer answers
+    pub(super) enabled: bool,
+    /// initial value of our timestamp clock
+    offset: u32,
+    /// origin of our timestamp clock
+    start: Instant,
+    /// timestamp to echo to the peer (TS.Recent)
+    recent: u32,
+    /// time `recent` was last updated at, if it ever was
+    recent_at: Option<Instant>,
+    /// acknowledgment number of the last segment we sent (Last.ACK.sent)
+    last_ack_sent: u32,
+}
+
+impl Timestamps {
+    pub(super) fn new(offset: u32) -> Self {
+        Timestamps {
+            enabled: true,
+            offset,
+            start: Instant::now(),
+            recent: 0,
+            recent_at: None,
+            last_ack_sent: 0,
+        }
+    }
+
+    /// Current value of our timestamp clock (TSval)
+    pub(super) fn now(&self) -> u32 {
+        self.offset
+            .wrapping_add(self.start.elapsed().as_millis() as u32)
+    }
+
+    /// Timestamp echoed to the peer (TSecr)
+    pub(super) fn recent(&self) -> u32 {
+        self.recent
+    }
+
+    /// Records the acknowledgment number of a segment that was just sent
+    pub(super) fn on_send(&mut self, ack: u32) {
+        self.last_ack_sent = ack;
+    }
+
+    /// Takes the timestamp of the peer's SYN as the one to echo
+    pub(super) fn set_recent(&mut self, tsval: u32, now: Instant) {
+        self.recent = tsval;
+        self.recent_at = Some(now);
+    }
+
+    /// Checks whether a segment's timestamp is older than the last one received, i.e. the
+    /// segment is an old duplicate. (RFC 7323 - Section 5.3 - R1)
+    pub(super) fn is_old(&self, tsval: u32, now: Instant) -> bool {
+        match self.recent_at {
+            Some(recent_at) if now - recent_at < RECENT_LIFETIME => is_after(self.recent, tsval),
+            _ => false,
+        }
+    }
+
+    /// Updates the timestamp to echo with the one of an acceptable segment starting at `seq`.
+    /// Only segments covering the last acknowledgment we sent are considered, so that delayed
+    /// acknowledgments echo the oldest timestamp. (RFC 7323 - Section 4.3)
+    pub(super) fn on_receive(&mut self, seq: u32, tsval: u32, now: Instant) {
+        let recent_valid = self
+            .recent_at
+            .is_some_and(|recent_at| now - recent_at < RECENT_LIFETIME);
+        if (!recent_valid || !is_after(self.recent, tsval)) && !is_after(seq, self.last_ack_sent) {
+            self.set_recent(tsval, now);
+        }
+    }
+
+    /// Measures the round-trip time from the echo of one of our timestamps.
+    /// (RFC 7323 - Section 4.1)
+    pub(super) fn rtt(&self, tsecr: u32) -> Duration {
+        Duration::from_millis(self.now().wrapping_sub(tsecr) as u64)
+    }
+}